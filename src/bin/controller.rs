@@ -1,6 +1,6 @@
 use std::net::UdpSocket;
 
-static TEXT: &'static str = include_str!("input.txt"); 
+static TEXT: &str = include_str!("input.txt"); 
 
 fn main() {
     let controller = UdpSocket::bind("127.32.68.101:54528").unwrap(); 
@@ -0,0 +1,87 @@
+use std::{env, net::{SocketAddr, UdpSocket}, thread::sleep, time::{Duration, Instant}};
+
+use our_game::mysocket::{self, MySocket, Received};
+
+const TIMEOUT: Duration = Duration::from_secs(1); 
+
+fn usage() -> ! {
+    eprintln!("usage: ping <local addr> <target addr> [count [size]]"); 
+    eprintln!("       ping --echo <local addr>"); 
+    std::process::exit(1)
+}
+
+/// answer every packet with the same payload, the target of a ping. 
+fn echo(local: &str) {
+    let socket = UdpSocket::bind(local).unwrap(); 
+    let mut contents = [0u8; 2500]; 
+    eprintln!("[INFO ] echo on {local}"); 
+    loop {
+        if let Some(Received::Data(len, from)) = MySocket.recv_message(&socket, &mut contents) {
+            let _ = MySocket.send(&socket, from, &contents[..len]); 
+        }
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect(); 
+    match args.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+        ["--echo", local] => echo(local), 
+        [local, target] => ping(local, target, 4, 4), 
+        [local, target, count] => ping(local, target, count.parse().unwrap_or_else(|_| usage()), 4), 
+        [local, target, count, size] => ping(local, target, count.parse().unwrap_or_else(|_| usage()), 
+            size.parse().ok().filter(|s| (4..=2400).contains(s)).unwrap_or_else(|| usage())), 
+        _ => usage(), 
+    }
+}
+
+/// `size` bytes of payload in each probe, the sequence number first, to find the path mtu with. 
+fn ping(local: &str, target: &str, count: u32, size: usize) {
+    let socket = UdpSocket::bind(local).unwrap(); 
+    socket.set_read_timeout(Some(TIMEOUT)).unwrap(); 
+    let target: SocketAddr = target.parse().unwrap_or_else(|_| usage()); 
+    let mut contents = [0u8; 2500]; 
+    let mut rtts = Vec::new(); 
+    for seq in 0..count {
+        let start = Instant::now(); 
+        let mut probe = vec![0u8; size]; 
+        probe[..4].copy_from_slice(&seq.to_le_bytes()); 
+        if MySocket.send(&socket, target, &probe).is_err() {
+            return 
+        }
+        // wait for the answer of this very probe, the late ones of the previous probes are skipped. 
+        loop {
+            let left = TIMEOUT.saturating_sub(start.elapsed()); 
+            if left.is_zero() {
+                println!("seq={seq} timeout"); 
+                break 
+            }
+            socket.set_read_timeout(Some(left)).unwrap(); 
+            match MySocket.recv_message(&socket, &mut contents) {
+                Some(Received::Data(len, from)) if len == size && from == target && contents[..4] == seq.to_le_bytes() => {
+                    let rtt = start.elapsed(); 
+                    println!("reply from {from}: seq={seq} time={:.3} ms", rtt.as_secs_f64() * 1000.); 
+                    rtts.push(rtt); 
+                    break 
+                }, 
+                Some(Received::Error { from, kind: mysocket::UNREACHABLE, code: mysocket::FRAG_NEEDED, target: t, mtu }) if SocketAddr::V4(t) == target => {
+                    println!("error from {from}: seq={seq} fragmentation needed, mtu {mtu}"); 
+                    break 
+                }, 
+                Some(Received::Error { from, kind, code, target: t, .. }) if SocketAddr::V4(t) == target => {
+                    println!("error from {from}: seq={seq} {}", mysocket::error_name(kind, code)); 
+                    break 
+                }, 
+                Some(_) => {}, 
+                None => {}, 
+            }
+        }
+        sleep(TIMEOUT.saturating_sub(start.elapsed())); 
+    }
+    let received = rtts.len() as u32; 
+    println!("--- {target} ping statistics ---"); 
+    println!("{count} sent, {received} received, {:.1}% loss", (count - received) as f64 * 100. / count.max(1) as f64); 
+    if let (Some(min), Some(max)) = (rtts.iter().min(), rtts.iter().max()) {
+        let avg = rtts.iter().sum::<Duration>() / received; 
+        println!("rtt min/avg/max = {:.3}/{:.3}/{:.3} ms", min.as_secs_f64() * 1000., avg.as_secs_f64() * 1000., max.as_secs_f64() * 1000.); 
+    }
+}
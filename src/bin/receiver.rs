@@ -6,10 +6,7 @@ fn main() {
     let hear = UdpSocket::bind("127.0.0.2:10256").unwrap(); 
     let mut contents = [0u8; 1500]; 
     let r = mysocket::MySocket.recv(&hear, &mut contents); 
-    match r {
-        Some((u, s)) => {
-            println!("[INFO ] Recvive from {s} info: {}", String::from_utf8_lossy(&contents[..u]));
-        },
-        None => {},
+    if let Some((u, s)) = r {
+        println!("[INFO ] Recvive from {s} info: {}", String::from_utf8_lossy(&contents[..u]));
     }
 }
\ No newline at end of file
@@ -1,7 +1,7 @@
-use std::{sync::{Arc, atomic::Ordering}, net::{SocketAddr, Ipv4Addr, SocketAddrV4}, str::FromStr};
+use std::{sync::{Arc, atomic::Ordering}, net::{SocketAddr, Ipv4Addr, SocketAddrV4}, str::FromStr, time::Duration};
 
-use our_game::router::{MESSAGE_LENGTH, CACHES, Router, MessageType, GLOBAL_ROUTERS, Message, config::drop_packet};
-use tokio::{runtime::Handle, net::UdpSocket};
+use our_game::{sim, shaper::{Conform, TokenBucket}, queue::{Codel, CodelConfig, CODEL_MAX_TIME, DropTail, Drr, DRR_MAX_WEIGHT, FqCodel, FQ_MAX_QUANTUM, Priority, QueueDiscipline, QueueLimit, Red, RedConfig}, mysocket::{self, CONTROL_LENGTH, EXTENDED_MARK, SendOptions}, routing::{self, Metric, Mode, Prefix}, router::{MESSAGE_LENGTH, HEADER_LENGTH, DEFAULT_QUEUE_SIZE, CACHES, write_error, Router, MessageType, GLOBAL_ROUTERS, Message, MessageKind, Link, Ecmp, Ecn, GilbertElliott, Jitter, ingress_router, config::{self, drop_packet}}};
+use tokio::{runtime::Handle, net::UdpSocket, time::sleep};
 
 const BUFFER_LENGTH: usize = MESSAGE_LENGTH; 
 
@@ -25,28 +25,33 @@ fn main() {
 async fn deal(input: &str, sender: Arc<UdpSocket>) {
     let mut this = None;
     let mut value: Option<usize> = None; 
+    // the link of the current router which the link-scoped commands (DELAY, ...) apply to. 
+    let mut link: Option<Ipv4Addr> = None; 
+    // recompute the routing tables once the whole command is done. 
+    let mut topology_changed = false; 
     for line in input.lines() {
         if let Some(ipv4) = line.strip_prefix("ROUTER ") {
             match Ipv4Addr::from_str(ipv4) {
                 Ok(ipv4) => {
                     this = Some(Router::from_ipv4addr(ipv4, sender.clone()).await); 
+                    link = None; 
                     if cfg!(feature = "log-deal") {
                         eprintln!("\x1b[36;1m[{:21} set router focus: {}\x1b[0m", "ROUTER SET", ipv4); 
                     }
                 },
                 Err(_) => {
                     this = None; 
+                    link = None; 
                     eprintln!("\x1b[33;1m[{:21}] ROUTER should follows a ipv4 str but meets: {}\x1b[0m", "Unknown Subcommand", ipv4); 
                 },
             }
         } else if let Some(bw) = line.strip_prefix("VALUE ") {
             value = bw.parse().ok(); 
             if let Some(0) = value { value = None; }; 
-            if value.is_none() {
-                eprintln!("\x1b[33;1m[{:21}] cause str: '{bw}'\x1b[0m", "Invalid Integer Parse"); 
-            } else {
-                if cfg!(feature = "log-deal") {
-                    eprintln!("\x1b[36;1m[{:21}] value: {}\x1b[0m", "Value Set", value.unwrap()); 
+            match value {
+                None => eprintln!("\x1b[33;1m[{:21}] cause str: '{bw}'\x1b[0m", "Invalid Integer Parse"), 
+                Some(v) => if cfg!(feature = "log-deal") {
+                    eprintln!("\x1b[36;1m[{:21}] value: {}\x1b[0m", "Value Set", v); 
                 }
             }
         } else if let Some(ipv4) = line.strip_prefix("LINK ") {
@@ -54,8 +59,12 @@ async fn deal(input: &str, sender: Arc<UdpSocket>) {
                 (Some(this), Some(bw), Ok(target)) => {
                     let other = Router::from_ipv4addr(target, sender.clone()).await; 
                     let mut outer = this.outers().lock().await; 
-                    outer.insert(target, (bw, other.sender().clone())); 
+                    outer.entry(target)
+                        .and_modify(|l| l.bandwidth = bw)
+                        .or_insert_with(|| Link::new(bw, *this.queue_limit.lock().unwrap(), other.sender().clone())); 
                     drop(outer); 
+                    link = Some(target); 
+                    topology_changed = true; 
                     if cfg!(feature = "log-deal") {
                         eprintln!("\x1b[36;1m[{:21}] {} -> {}, bw: {}\x1b[0m", "Update Link", this.ipv4addr(), other.ipv4addr(), bw); 
                     }
@@ -64,18 +73,495 @@ async fn deal(input: &str, sender: Arc<UdpSocket>) {
                     eprintln!("\x1b[31;1m[{:21}]\x1b[0m", "Link Update Error"); 
                 }
             }
-        } else if let Some(oval) = line.strip_prefix("QUEUE") {
-            let val: Option<usize> = oval.parse().ok(); 
-            match val {
-                Some(s) => {
-                    match this {
-                        Some(ref r) => {
-                            r.queue_size.store(s, Ordering::Relaxed); 
-                        }
-                        None => eprintln!("\x1b[33;1m[{:21}] this router not determined. \x1b[0m", "Invalid Queue Set")
+        } else if let Some(ms) = line.strip_prefix("DELAY ") {
+            match (&this, link, ms.trim().parse::<u64>()) {
+                (Some(this), Some(target), Ok(ms)) => {
+                    let mut outer = this.outers().lock().await; 
+                    if let Some(l) = outer.get_mut(&target) {
+                        l.delay = Duration::from_millis(ms); 
+                    }
+                    drop(outer); 
+                    topology_changed = true; 
+                    if cfg!(feature = "log-deal") {
+                        eprintln!("\x1b[36;1m[{:21}] {} -> {}, delay: {}ms\x1b[0m", "Update Link Delay", this.ipv4addr(), target, ms); 
+                    }
+                }
+                (_, _, Err(_)) => {
+                    eprintln!("\x1b[33;1m[{:21}] cause str: '{ms}'\x1b[0m", "Invalid Integer Parse"); 
+                }
+                _ => {
+                    eprintln!("\x1b[33;1m[{:21}] no link determined, use LINK first. \x1b[0m", "Invalid Delay Set"); 
+                }
+            }
+        } else if let Some(p) = line.strip_prefix("LOSS ") {
+            match (&this, link, p.trim().parse::<f64>()) {
+                (Some(this), Some(target), Ok(p)) if (0. ..=1.).contains(&p) => {
+                    let mut outer = this.outers().lock().await; 
+                    if let Some(l) = outer.get_mut(&target) {
+                        l.loss = p; 
+                    }
+                    drop(outer); 
+                    if cfg!(feature = "log-deal") {
+                        eprintln!("\x1b[36;1m[{:21}] {} -> {}, loss: {}\x1b[0m", "Update Link Loss", this.ipv4addr(), target, p); 
+                    }
+                }
+                (Some(_), Some(_), _) => {
+                    eprintln!("\x1b[33;1m[{:21}] probability should be in [0, 1], cause str: '{p}'\x1b[0m", "Invalid Loss Set"); 
+                }
+                _ => {
+                    eprintln!("\x1b[33;1m[{:21}] no link determined, use LINK first. \x1b[0m", "Invalid Loss Set"); 
+                }
+            }
+        } else if let Some(args) = line.strip_prefix("GILBERT ") {
+            // GILBERT <good->bad> <bad->good> <loss in good> <loss in bad>, or GILBERT OFF
+            let model = if args.trim() == "OFF" {
+                Some(None)
+            } else {
+                let ps: Option<Vec<f64>> = args.split_whitespace().map(|p| p.parse().ok().filter(|p| (0. ..=1.).contains(p))).collect(); 
+                match ps.as_deref() {
+                    Some(&[gb, bg, lg, lb]) => Some(Some(GilbertElliott::new(gb, bg, lg, lb))), 
+                    _ => None, 
+                }
+            }; 
+            match (&this, link, model) {
+                (Some(this), Some(target), Some(model)) => {
+                    let mut outer = this.outers().lock().await; 
+                    if let Some(l) = outer.get_mut(&target) {
+                        l.gilbert = model; 
+                    }
+                    drop(outer); 
+                    if cfg!(feature = "log-deal") {
+                        eprintln!("\x1b[36;1m[{:21}] {} -> {}, model: {:?}\x1b[0m", "Update Link Gilbert", this.ipv4addr(), target, model); 
+                    }
+                }
+                (Some(_), Some(_), None) => {
+                    eprintln!("\x1b[33;1m[{:21}] needs 4 probabilities in [0, 1] or OFF, cause str: '{args}'\x1b[0m", "Invalid Gilbert Set"); 
+                }
+                _ => {
+                    eprintln!("\x1b[33;1m[{:21}] no link determined, use LINK first. \x1b[0m", "Invalid Gilbert Set"); 
+                }
+            }
+        } else if let Some(args) = line.strip_prefix("JITTER ") {
+            // JITTER UNIFORM <ms>, JITTER NORMAL <stddev ms>, or JITTER OFF
+            let mut words = args.split_whitespace(); 
+            let jitter = match (words.next(), words.next().map(str::parse::<u64>)) {
+                (Some("OFF"), None) => Some(Jitter::None), 
+                (Some("UNIFORM"), Some(Ok(ms))) => Some(Jitter::Uniform(Duration::from_millis(ms))), 
+                (Some("NORMAL"), Some(Ok(ms))) => Some(Jitter::Normal(Duration::from_millis(ms))), 
+                _ => None, 
+            }; 
+            match (&this, link, jitter) {
+                (Some(this), Some(target), Some(jitter)) => {
+                    let mut outer = this.outers().lock().await; 
+                    if let Some(l) = outer.get_mut(&target) {
+                        l.jitter = jitter; 
+                    }
+                    drop(outer); 
+                    if cfg!(feature = "log-deal") {
+                        eprintln!("\x1b[36;1m[{:21}] {} -> {}, jitter: {:?}\x1b[0m", "Update Link Jitter", this.ipv4addr(), target, jitter); 
+                    }
+                }
+                (Some(_), Some(_), None) => {
+                    eprintln!("\x1b[33;1m[{:21}] needs UNIFORM <ms>, NORMAL <ms> or OFF, cause str: '{args}'\x1b[0m", "Invalid Jitter Set"); 
+                }
+                _ => {
+                    eprintln!("\x1b[33;1m[{:21}] no link determined, use LINK first. \x1b[0m", "Invalid Jitter Set"); 
+                }
+            }
+        } else if let Some(p) = line.strip_prefix("REORDER ") {
+            match (&this, link, p.trim().parse::<f64>()) {
+                (Some(this), Some(target), Ok(p)) if (0. ..=1.).contains(&p) => {
+                    let mut outer = this.outers().lock().await; 
+                    if let Some(l) = outer.get_mut(&target) {
+                        l.reorder = p; 
+                    }
+                    drop(outer); 
+                    if cfg!(feature = "log-deal") {
+                        eprintln!("\x1b[36;1m[{:21}] {} -> {}, reorder: {}\x1b[0m", "Update Link Reorder", this.ipv4addr(), target, p); 
+                    }
+                }
+                (Some(_), Some(_), _) => {
+                    eprintln!("\x1b[33;1m[{:21}] probability should be in [0, 1], cause str: '{p}'\x1b[0m", "Invalid Reorder Set"); 
+                }
+                _ => {
+                    eprintln!("\x1b[33;1m[{:21}] no link determined, use LINK first. \x1b[0m", "Invalid Reorder Set"); 
+                }
+            }
+        } else if let Some((name, p)) = line.strip_prefix("DUPLICATE ").map(|p| ("Duplicate", p))
+            .or_else(|| line.strip_prefix("CORRUPT ").map(|p| ("Corrupt", p))) {
+            match (&this, link, p.trim().parse::<f64>()) {
+                (Some(this), Some(target), Ok(p)) if (0. ..=1.).contains(&p) => {
+                    let mut outer = this.outers().lock().await; 
+                    if let Some(l) = outer.get_mut(&target) {
+                        if name == "Duplicate" { l.duplicate = p; } else { l.corrupt = p; }
+                    }
+                    drop(outer); 
+                    if cfg!(feature = "log-deal") {
+                        eprintln!("\x1b[36;1m[{:21}] {} -> {}, {}: {}\x1b[0m", format!("Update Link {name}"), this.ipv4addr(), target, name.to_lowercase(), p); 
+                    }
+                }
+                (Some(_), Some(_), _) => {
+                    eprintln!("\x1b[33;1m[{:21}] probability should be in [0, 1], cause str: '{p}'\x1b[0m", format!("Invalid {name} Set")); 
+                }
+                _ => {
+                    eprintln!("\x1b[33;1m[{:21}] no link determined, use LINK first. \x1b[0m", format!("Invalid {name} Set")); 
+                }
+            }
+        } else if let Some(seed) = line.strip_prefix("SEED ") {
+            match seed.trim().parse::<u64>() {
+                Ok(seed) => {
+                    config::SEED.store(seed, Ordering::Relaxed); 
+                    for r in GLOBAL_ROUTERS.lock().await.values() {
+                        r.reseed().await; 
+                    }
+                    if cfg!(feature = "log-deal") {
+                        eprintln!("\x1b[36;1m[{:21}] seed: {seed}\x1b[0m", "Seed Set"); 
+                    }
+                },
+                Err(_) => {
+                    eprintln!("\x1b[33;1m[{:21}] cause str: '{seed}'\x1b[0m", "Invalid Integer Parse"); 
+                },
+            }
+        } else if let Some(switch) = line.strip_prefix("ERRORS ") {
+            // ERRORS ON|OFF, the routers report the packets they drop for a missing route, a missing 
+            // router, the TTL, the mtu or the reassembly timeout back to the source
+            match switch.trim() {
+                s @ ("ON" | "OFF") => {
+                    config::ERRORS.store(s == "ON", Ordering::Relaxed); 
+                    if cfg!(feature = "log-deal") {
+                        eprintln!("\x1b[36;1m[{:21}] {s}\x1b[0m", "Error Report Set"); 
+                    }
+                },
+                _ => {
+                    eprintln!("\x1b[33;1m[{:21}] needs ON or OFF, cause str: '{switch}'\x1b[0m", "Invalid Errors Set"); 
+                },
+            }
+        } else if let Some(switch) = line.strip_prefix("FRAGMENT ") {
+            // FRAGMENT ON|OFF, the routers split the packets over the mtu of a link and the target router 
+            // puts them together, or drop them with a fragmentation needed error 
+            match switch.trim() {
+                s @ ("ON" | "OFF") => {
+                    config::FRAGMENT.store(s == "ON", Ordering::Relaxed); 
+                    if cfg!(feature = "log-deal") {
+                        eprintln!("\x1b[36;1m[{:21}] {s}\x1b[0m", "Fragment Set"); 
+                    }
+                },
+                _ => {
+                    eprintln!("\x1b[33;1m[{:21}] needs ON or OFF, cause str: '{switch}'\x1b[0m", "Invalid Fragment Set"); 
+                },
+            }
+        } else if let Some(mtu) = line.strip_prefix("MTU ") {
+            // MTU <bytes>|OFF, the largest packet of the current LINK, the address header included 
+            let val = match mtu.trim() {
+                "OFF" => Some(None), 
+                v => v.parse::<usize>().ok().filter(|v| (HEADER_LENGTH + CONTROL_LENGTH..=MESSAGE_LENGTH).contains(v)).map(Some), 
+            }; 
+            match (&this, link, val) {
+                (Some(this), Some(target), Some(val)) => {
+                    let mut outer = this.outers().lock().await; 
+                    if let Some(l) = outer.get_mut(&target) {
+                        l.mtu = val; 
+                    }
+                    drop(outer); 
+                    if cfg!(feature = "log-deal") {
+                        eprintln!("\x1b[36;1m[{:21}] {} -> {}, mtu: {:?}\x1b[0m", "Update Link Mtu", this.ipv4addr(), target, val); 
+                    }
+                }
+                (Some(_), Some(_), None) => {
+                    eprintln!("\x1b[33;1m[{:21}] should be OFF or in [{}, {MESSAGE_LENGTH}], cause str: '{mtu}'\x1b[0m", "Invalid Mtu Set", HEADER_LENGTH + CONTROL_LENGTH); 
+                }
+                _ => {
+                    eprintln!("\x1b[33;1m[{:21}] no link determined, use LINK first. \x1b[0m", "Invalid Mtu Set"); 
+                }
+            }
+        } else if let Some(ttl) = line.strip_prefix("TTL ") {
+            match ttl.trim().parse::<usize>() {
+                Ok(ttl) if ttl > 0 => {
+                    config::MAX_HOPS.store(ttl, Ordering::Relaxed); 
+                    if cfg!(feature = "log-deal") {
+                        eprintln!("\x1b[36;1m[{:21}] max hops: {ttl}\x1b[0m", "TTL Set"); 
+                    }
+                },
+                _ => {
+                    eprintln!("\x1b[33;1m[{:21}] cause str: '{ttl}'\x1b[0m", "Invalid TTL Parse"); 
+                },
+            }
+        } else if let Some(args) = line.strip_prefix("ROUTING ") {
+            // ROUTING PERIODIC, ROUTING GLOBAL <metric>, ROUTING DV, or ROUTING LS <metric>, 
+            // where the metric is HOPS, BANDWIDTH or DELAY
+            let mut words = args.split_whitespace(); 
+            let mode = match (words.next(), words.next().map(Metric::parse)) {
+                (Some("PERIODIC"), None) => Some(Mode::Periodic), 
+                (Some("GLOBAL"), Some(Some(metric))) => Some(Mode::Global(metric)), 
+                (Some("DV"), None) => Some(Mode::DistanceVector), 
+                (Some("LS"), Some(Some(metric))) => Some(Mode::LinkState(metric)), 
+                _ => None, 
+            }; 
+            match mode {
+                Some(mode) => {
+                    routing::set_mode(mode); 
+                    topology_changed = true; 
+                    if cfg!(feature = "log-deal") {
+                        eprintln!("\x1b[36;1m[{:21}] mode: {:?}\x1b[0m", "Routing Mode Set", mode); 
                     }
                 },
                 None => {
+                    eprintln!("\x1b[33;1m[{:21}] needs PERIODIC, GLOBAL <metric>, DV or LS <metric>, cause str: '{args}'\x1b[0m", "Invalid Routing Set"); 
+                },
+            }
+        } else if let Some(args) = line.strip_prefix("DV ") {
+            // DV SPLIT <ON|OFF>, DV POISON <ON|OFF>, or DV INFINITY <n>
+            let mut config = routing::dv_config(); 
+            let mut words = args.split_whitespace(); 
+            let ok = match (words.next(), words.next()) {
+                (Some("SPLIT"), Some(v @ ("ON" | "OFF"))) => { config.split_horizon = v == "ON"; true }, 
+                (Some("POISON"), Some(v @ ("ON" | "OFF"))) => { config.poison_reverse = v == "ON"; true }, 
+                (Some("INFINITY"), Some(n)) => match n.parse::<u16>() {
+                    Ok(n) if n > 1 => { config.infinity = n; true }, 
+                    _ => false, 
+                }, 
+                _ => false, 
+            }; 
+            if ok {
+                routing::set_dv_config(config); 
+                if cfg!(feature = "log-deal") {
+                    eprintln!("\x1b[36;1m[{:21}] {:?}\x1b[0m", "Distance Vector Set", config); 
+                }
+            } else {
+                eprintln!("\x1b[33;1m[{:21}] needs SPLIT <ON|OFF>, POISON <ON|OFF> or INFINITY <n>, cause str: '{args}'\x1b[0m", "Invalid DV Set"); 
+            }
+        } else if let Some(args) = line.strip_prefix("ROUTE ") {
+            // ROUTE <dest> VIA <nexthop> [<nexthop> ...], or ROUTE DEL <dest>, where dest is a prefix 
+            // (a.b.c.d/n), an address, or DEFAULT
+            let words: Vec<&str> = args.split_whitespace().collect(); 
+            let prefix = |dest: &str| if dest == "DEFAULT" { Ok(Prefix::DEFAULT) } else { Prefix::from_str(dest) }; 
+            match (&this, words.as_slice()) {
+                (Some(this), ["DEL", dest]) => match prefix(dest) {
+                    Ok(dest) => {
+                        let removed = this.remove_static_route(dest).await; 
+                        if !removed {
+                            eprintln!("\x1b[33;1m[{:21}] no static route to {dest} at {}\x1b[0m", "Invalid Route Delete", this.ipv4addr()); 
+                        } else if cfg!(feature = "log-deal") {
+                            eprintln!("\x1b[36;1m[{:21}] {}: {dest}\x1b[0m", "Static Route Delete", this.ipv4addr()); 
+                        }
+                    }, 
+                    Err(_) => eprintln!("\x1b[33;1m[{:21}] cause str: '{dest}'\x1b[0m", "Invalid Prefix Parse"), 
+                }, 
+                (Some(this), [dest, "VIA", hops @ ..]) if !hops.is_empty() => {
+                    let hops: Result<Vec<Ipv4Addr>, _> = hops.iter().map(|h| Ipv4Addr::from_str(h)).collect(); 
+                    match (prefix(dest), hops) {
+                        (Ok(dest), Ok(hops)) => {
+                            let outer = this.outers().lock().await; 
+                            let stranger = hops.iter().find(|h| !outer.contains_key(h)).copied(); 
+                            drop(outer); 
+                            match stranger {
+                                Some(hop) => eprintln!("\x1b[33;1m[{:21}] {hop} is not a neighbor of {}\x1b[0m", "Invalid Route Set", this.ipv4addr()), 
+                                None => {
+                                    if cfg!(feature = "log-deal") {
+                                        eprintln!("\x1b[36;1m[{:21}] {}: {dest} via {hops:?}\x1b[0m", "Static Route Set", this.ipv4addr()); 
+                                    }
+                                    this.add_static_route(dest, hops).await; 
+                                }, 
+                            }
+                        }, 
+                        _ => eprintln!("\x1b[33;1m[{:21}] cause str: '{args}'\x1b[0m", "Invalid Prefix Parse"), 
+                    }
+                }, 
+                (Some(_), _) => {
+                    eprintln!("\x1b[33;1m[{:21}] needs <dest> VIA <nexthop> or DEL <dest>, cause str: '{args}'\x1b[0m", "Invalid Route Set"); 
+                }
+                (None, _) => {
+                    eprintln!("\x1b[33;1m[{:21}] this router not determined. \x1b[0m", "Invalid Route Set"); 
+                }
+            }
+        } else if let Some(prefix) = line.strip_prefix("SUBNET ") {
+            // the current router owns the host addresses in the prefix
+            match (&this, Prefix::from_str(prefix.trim())) {
+                (Some(this), Ok(prefix)) => {
+                    let mut subnets = this.subnets().lock().await; 
+                    if !subnets.contains(&prefix) {
+                        subnets.push(prefix); 
+                    }
+                    drop(subnets); 
+                    topology_changed = true; 
+                    if cfg!(feature = "log-deal") {
+                        eprintln!("\x1b[36;1m[{:21}] {} owns {prefix}\x1b[0m", "Subnet Set", this.ipv4addr()); 
+                    }
+                }, 
+                (Some(_), Err(_)) => {
+                    eprintln!("\x1b[33;1m[{:21}] cause str: '{prefix}'\x1b[0m", "Invalid Prefix Parse"); 
+                }, 
+                (None, _) => {
+                    eprintln!("\x1b[33;1m[{:21}] this router not determined. \x1b[0m", "Invalid Subnet Set"); 
+                }, 
+            }
+        } else if let Some(mode) = line.strip_prefix("ECMP ") {
+            // ECMP FLOW (hash of source and target), or ECMP PACKET (round robin)
+            let ecmp = match mode.trim() {
+                "FLOW" => Some(Ecmp::Flow), 
+                "PACKET" => Some(Ecmp::Packet), 
+                _ => None, 
+            }; 
+            match (&this, ecmp) {
+                (Some(this), Some(ecmp)) => {
+                    *this.ecmp.lock().unwrap() = ecmp; 
+                    if cfg!(feature = "log-deal") {
+                        eprintln!("\x1b[36;1m[{:21}] {}: {:?}\x1b[0m", "ECMP Set", this.ipv4addr(), ecmp); 
+                    }
+                }, 
+                (Some(_), None) => {
+                    eprintln!("\x1b[33;1m[{:21}] needs FLOW or PACKET, cause str: '{mode}'\x1b[0m", "Invalid ECMP Set"); 
+                }, 
+                (None, _) => {
+                    eprintln!("\x1b[33;1m[{:21}] this router not determined. \x1b[0m", "Invalid ECMP Set"); 
+                }, 
+            }
+        } else if let Some(args) = line.strip_prefix("QDISC ") {
+            // QDISC DROPTAIL, QDISC RED <min threshold> <max threshold> <max_p> <weight>, 
+            // QDISC CODEL [<target ms> <interval ms>], or QDISC FQCODEL [<target ms> <interval ms> [<quantum bytes>]], 
+            // QDISC PRIO <classes>, or QDISC DRR <weight of class 0> <weight of class 1> ..., 
+            // the queue discipline of the current LINK. RED, CODEL and FQCODEL take a last word ECN to mark 
+            // the ECN-capable packets instead of dropping them
+            let mut words: Vec<&str> = args.split_whitespace().collect(); 
+            let ecn = words.last() == Some(&"ECN"); 
+            if ecn {
+                words.pop(); 
+            }
+            let ps: Option<Vec<f64>> = words.iter().skip(1).map(|p| p.parse().ok()).collect(); 
+            let limit = this.as_ref().map_or(QueueLimit::packets(DEFAULT_QUEUE_SIZE), |r| *r.queue_limit.lock().unwrap()); 
+            let queue: Option<Box<dyn QueueDiscipline>> = match (words.first().copied(), ps.as_deref()) {
+                (Some("DROPTAIL"), Some([])) if !ecn => Some(Box::new(DropTail::new(limit))), 
+                (Some("RED"), Some(&[min_threshold, max_threshold, max_p, weight])) 
+                    if 0. <= min_threshold && min_threshold < max_threshold && (0. ..=1.).contains(&max_p) && 0. < weight && weight <= 1. => 
+                    Some(Box::new(Red::new(limit, RedConfig { min_threshold, max_threshold, max_p, weight, ecn }))), 
+                (Some("CODEL"), Some(ps)) => codel_config(ps, ecn).map(|(c, _)| Box::new(Codel::new(limit, c)) as Box<dyn QueueDiscipline>), 
+                (Some("FQCODEL"), Some(ps)) => codel_config(ps, ecn).map(|(c, quantum)| Box::new(FqCodel::new(limit, c, quantum)) as Box<dyn QueueDiscipline>), 
+                (Some("PRIO"), Some(&[classes])) if !ecn && (1. ..=256.).contains(&classes) && classes.fract() == 0. => 
+                    Some(Box::new(Priority::new(limit, classes as usize))), 
+                (Some("DRR"), Some(weights)) if !ecn && !weights.is_empty() && weights.len() <= 256 && weights.iter().all(|w| (1. ..=DRR_MAX_WEIGHT as f64).contains(w) && w.fract() == 0.) => 
+                    Some(Box::new(Drr::new(limit, weights.iter().map(|w| *w as usize).collect()))), 
+                _ => None, 
+            }; 
+            match (&this, link, queue) {
+                (Some(this), Some(target), Some(queue)) => {
+                    let name = queue.stats(); 
+                    this.set_discipline(target, queue).await; 
+                    if cfg!(feature = "log-deal") {
+                        eprintln!("\x1b[36;1m[{:21}] {} -> {}, {name}\x1b[0m", "Update Link Qdisc", this.ipv4addr(), target); 
+                    }
+                }
+                (Some(_), Some(_), None) => {
+                    eprintln!("\x1b[33;1m[{:21}] needs DROPTAIL, RED <min> <max> <max_p> <weight>, CODEL, FQCODEL, PRIO <classes> or DRR <weights>, cause str: '{args}'\x1b[0m", "Invalid Qdisc Set"); 
+                }
+                _ => {
+                    eprintln!("\x1b[33;1m[{:21}] no link determined, use LINK first. \x1b[0m", "Invalid Qdisc Set"); 
+                }
+            }
+        } else if let Some(args) = line.strip_prefix("CLASSIFY ") {
+            // CLASSIFY <destination port> <priority>, or CLASSIFY <destination port> OFF, the priority of 
+            // the packets whose client did not ask for one
+            let mut words = args.split_whitespace(); 
+            let port = words.next().map(str::parse::<u16>); 
+            let priority = match words.next().map(|w| (w, w.parse::<u8>())) {
+                Some(("OFF", _)) => Some(None), 
+                Some((_, Ok(p))) => Some(Some(p)), 
+                _ => None, 
+            }; 
+            match (port, priority, words.next()) {
+                (Some(Ok(port)), Some(priority), None) => {
+                    let mut classes = config::PORT_CLASSES.lock().unwrap(); 
+                    match priority {
+                        Some(p) => classes.insert(port, p), 
+                        None => classes.remove(&port), 
+                    }; 
+                    if cfg!(feature = "log-deal") {
+                        eprintln!("\x1b[36;1m[{:21}] port {port}: priority {priority:?}\x1b[0m", "Classify Set"); 
+                    }
+                }, 
+                _ => {
+                    eprintln!("\x1b[33;1m[{:21}] needs <port> <priority> or <port> OFF, cause str: '{args}'\x1b[0m", "Invalid Classify Set"); 
+                }, 
+            }
+        } else if let Some((conform, args)) = ["SHAPE", "POLICE"].into_iter().find_map(|c| line.strip_prefix(c)?.strip_prefix(' ').map(|a| (c, a))) {
+            // SHAPE <host> <rate bits/s> <burst bytes> [<max delay ms>], POLICE <host> <rate bits/s> <burst bytes>, 
+            // or SHAPE|POLICE <host> OFF, the rate plan of a source host attached to this router
+            let words: Vec<&str> = args.split_whitespace().collect(); 
+            let host = words.first().map(|h| Ipv4Addr::from_str(h)); 
+            let rate = words.get(1).and_then(|r| r.parse::<usize>().ok()).filter(|r| *r > 0); 
+            let burst = words.get(2).and_then(|b| b.parse::<usize>().ok()); 
+            let bucket = match (conform, &words[..], rate, burst) {
+                (_, [_, "OFF"], _, _) => Some(None), 
+                ("POLICE", [_, _, _], Some(rate), Some(burst)) => Some(Some((rate, burst, Conform::Police))), 
+                ("SHAPE", [_, _, _], Some(rate), Some(burst)) => Some(Some((rate, burst, Conform::Shape(Duration::from_secs(1))))), 
+                ("SHAPE", [_, _, _, ms], Some(rate), Some(burst)) => ms.parse::<u64>().ok()
+                    .map(|ms| Some((rate, burst, Conform::Shape(Duration::from_millis(ms))))), 
+                _ => None, 
+            }; 
+            match (&this, host, bucket) {
+                (Some(this), Some(Ok(host)), Some(bucket)) => {
+                    let mut buckets = this.buckets.lock().await; 
+                    match bucket {
+                        Some((rate, burst, conform)) => buckets.insert(host, TokenBucket::new(rate, burst, conform, sim::ingress_now())), 
+                        None => buckets.remove(&host), 
+                    }; 
+                    if cfg!(feature = "log-deal") {
+                        eprintln!("\x1b[36;1m[{:21}] {}: {host} {:?}\x1b[0m", "Rate Plan Set", this.ipv4addr(), buckets.get(&host).map(TokenBucket::stats)); 
+                    }
+                }, 
+                (None, _, _) => {
+                    eprintln!("\x1b[33;1m[{:21}] this router not determined. \x1b[0m", "Invalid Rate Plan Set"); 
+                }, 
+                _ => {
+                    eprintln!("\x1b[33;1m[{:21}] needs <host> <rate> <burst> or <host> OFF, cause str: '{args}'\x1b[0m", "Invalid Rate Plan Set"); 
+                }, 
+            }
+        } else if line.trim() == "STATS" {
+            // the counters of the whole network, then the queue of every link
+            eprintln!("\x1b[36;1m[{:21}] received {} packets ({} bytes), lost {} packets ({} bytes), {} over the hop limit, {} fragmented, {} reassembled\x1b[0m", "Stats", 
+                config::RECEIVE_PACKETS.load(Ordering::Relaxed), config::RECEIVE_BYTES.load(Ordering::Relaxed), 
+                config::LOSS_PACKETS.load(Ordering::Relaxed), config::LOSS_BYTES.load(Ordering::Relaxed), 
+                config::LOOP_PACKETS.load(Ordering::Relaxed), config::FRAGMENTED_PACKETS.load(Ordering::Relaxed), 
+                config::REASSEMBLED_PACKETS.load(Ordering::Relaxed)); 
+            for r in GLOBAL_ROUTERS.lock().await.values() {
+                for (host, bucket) in r.buckets.lock().await.iter() {
+                    eprintln!("\x1b[36;1m[{:21}] {} from {host}: {}\x1b[0m", "Stats Rate Plan", r.ipv4addr(), bucket.stats()); 
+                }
+                eprintln!("\x1b[36;1m[{:21}] {}: {} bytes queued, peak {} bytes\x1b[0m", "Stats Router", r.ipv4addr(), 
+                    r.queued_bytes().await, r.peak_bytes.load(Ordering::Relaxed)); 
+                for (target, l) in r.outers().lock().await.iter() {
+                    eprintln!("\x1b[36;1m[{:21}] {} -> {target}: {} packets, {} bytes queued, limit {}, {}\x1b[0m", "Stats Link", r.ipv4addr(), 
+                        l.queue.len(), l.queue.bytes(), l.queue.limit(), l.queue.stats()); 
+                }
+            }
+        } else if let Some((unit, oval)) = ["QUEUE", "QUEUEBYTES"].into_iter().find_map(|c| line.strip_prefix(c)?.strip_prefix(' ').map(|a| (c, a))) {
+            // QUEUE <packets> or QUEUEBYTES <bytes>, either OFF to lift it, with both the queue is full at 
+            // whichever is reached first. the limit of the current LINK, or without one of every link of 
+            // the router 
+            let val: Option<Option<usize>> = match oval.trim() {
+                "OFF" => Some(None), 
+                v => v.parse().ok().map(Some), 
+            }; 
+            match (&this, val) {
+                (Some(r), Some(v)) => {
+                    let update = |mut limit: QueueLimit| {
+                        if unit == "QUEUE" { limit.packets = v } else { limit.bytes = v }; 
+                        limit
+                    }; 
+                    let mut outer = r.outers().lock().await; 
+                    let links: Vec<&mut Link> = match link {
+                        Some(target) => outer.get_mut(&target).into_iter().collect(), 
+                        None => {
+                            let mut default = r.queue_limit.lock().unwrap(); 
+                            *default = update(*default); 
+                            outer.values_mut().collect()
+                        }, 
+                    }; 
+                    for l in links {
+                        l.queue.set_limit(update(l.queue.limit())); 
+                    }
+                }, 
+                (None, _) => eprintln!("\x1b[33;1m[{:21}] this router not determined. \x1b[0m", "Invalid Queue Set"), 
+                (_, None) => {
                     eprintln!("\x1b[33;1m[{:21}] size invalid, cause: {oval}\x1b[0m", "Invalid Queue Set"); 
                 },
             }
@@ -84,13 +570,53 @@ async fn deal(input: &str, sender: Arc<UdpSocket>) {
             eprintln!("\x1b[33;1m[{:21}] cause: {line}\x1b[0m", "Control Command Unknown"); 
         }
     }
+    if topology_changed {
+        routing::recompute().await; 
+    }
+}
+
+/// the target and interval (in ms, up to `CODEL_MAX_TIME`) and the quantum (in bytes, up to 
+/// `FQ_MAX_QUANTUM`) of the CODEL and FQCODEL commands, each may be left out for its default. 
+fn codel_config(ps: &[f64], ecn: bool) -> Option<(CodelConfig, usize)> {
+    let default = CodelConfig::default(); 
+    let ms = |i: usize, d: Duration| ps.get(i).map_or(Some(d), |&ms| Duration::try_from_secs_f64(ms / 1000.).ok()
+        .filter(|t| !t.is_zero() && *t <= CODEL_MAX_TIME)); 
+    let quantum = ps.get(2).map_or(Some(1514), |&q| (1. ..=FQ_MAX_QUANTUM as f64).contains(&q).then_some(q as usize)); 
+    if ps.len() > 3 {
+        return None
+    }
+    Some((CodelConfig { target: ms(0, default.target)?, interval: ms(1, default.interval)?, ecn }, quantum?))
 }
 
 async fn exec(rt: &Handle) {
+    // --virtual: drive the routers by the discrete-event simulator on a virtual clock; 
+    // --fast: with --virtual, do not wait for the wall clock while packets are in flight. 
+    // --seed <u64>: seed of the random behaviors, a random one is picked (and printed) otherwise. 
+    let args: Vec<String> = std::env::args().skip(1).collect(); 
+    let mut seed = None; 
+    let mut iter = args.iter(); 
+    while let Some(a) = iter.next() {
+        match a.as_str() {
+            "--virtual" => sim::enable(args.iter().any(|a| a == "--fast")), 
+            "--fast" => {}, 
+            "--seed" => match iter.next().map(|s| s.parse::<u64>()) {
+                Some(Ok(s)) => seed = Some(s), 
+                _ => eprintln!("\x1b[33;1m[{:21}] --seed should follow a u64\x1b[0m", "Argument Invalid"), 
+            }, 
+            _ => eprintln!("\x1b[33;1m[{:21}] cause: {a}\x1b[0m", "Argument Unknown"), 
+        }
+    }
+    let seed = seed.unwrap_or_else(rand::random); 
+    config::SEED.store(seed, Ordering::Relaxed); 
+    eprintln!("\x1b[36;1m[{:21}] seed: {seed}\x1b[0m", "Random Seed"); 
     let core_socket = UdpSocket::bind("127.67.117.116:52736").await.unwrap();
     let controller_address: SocketAddrV4 = SocketAddrV4::from_str("127.32.68.101:54528").unwrap(); 
     eprintln!("\x1b[36;1m[{:21}] udp addr: {}\x1b[0m", "Server Boot", core_socket.local_addr().unwrap()); 
     let core_socket = Arc::new(core_socket); 
+    if sim::enabled() {
+        eprintln!("\x1b[36;1m[{:21}] virtual time{}\x1b[0m", "Simulator Boot", if args.iter().any(|a| a == "--fast") { ", fast" } else { "" }); 
+        rt.spawn(sim::run(core_socket.clone())); 
+    }
     loop {
         let mut buffer; 
         let mut bq = CACHES.lock().await; 
@@ -117,8 +643,9 @@ async fn exec(rt: &Handle) {
                 }
             },
             SocketAddr::V4(client) => {
+                let sender = core_socket.clone(); 
                 rt.spawn(async move {
-                    push_in_network(buffer, length, client).await; 
+                    push_in_network(buffer, length, client, sender).await; 
                 }); 
             }
             _ => {
@@ -128,7 +655,7 @@ async fn exec(rt: &Handle) {
     }
 }
 
-pub async fn push_in_network(mut buffer: MessageType, message_length: usize, from_ip: SocketAddrV4) {
+pub async fn push_in_network(mut buffer: MessageType, mut message_length: usize, from_ip: SocketAddrV4, sender: Arc<UdpSocket>) {
     assert! (buffer.len() >= message_length); 
     if message_length < 6 {
         drop_packet(message_length, "carefully when you send to the emulator", buffer).await; 
@@ -137,30 +664,69 @@ pub async fn push_in_network(mut buffer: MessageType, message_length: usize, fro
     if cfg!(feature = "log-packet") {
         eprintln!("\x1b[32;1m[{:21}] from: {from_ip}\x1b[0m", "Receive Packet"); 
     }
-    let global_router = GLOBAL_ROUTERS.lock().await; 
-    let r; 
-    match global_router.get(from_ip.ip()) {
+    let mut options = SendOptions::default(); 
+    if buffer[..4] == EXTENDED_MARK {
+        // extended header: mark, options length, target, options; it shrinks to the plain one. 
+        let extended = 12 + (buffer[4] as usize + ((buffer[5] as usize) << 8)); 
+        if message_length < extended {
+            drop_packet(message_length, "extended header longer than the packet", buffer).await; 
+            return 
+        }
+        options = SendOptions::decode(&buffer[12..extended]); 
+        buffer.copy_within(6..12, 0); 
+        buffer.copy_within(extended..message_length, HEADER_LENGTH); 
+        message_length -= extended - HEADER_LENGTH; 
+    }
+    let target_addr = SocketAddrV4::new(Ipv4Addr::new(buffer[0], buffer[1], buffer[2], buffer[3]), 
+        buffer[4] as u16 + (( buffer[5] as u16 ) << 8)); 
+    let r = match ingress_router(*from_ip.ip()).await {
         Some(router) => {
-            r = router.clone(); 
+            router
         },
         None => {
+            if config::ERRORS.load(Ordering::Relaxed) {
+                // no router to report from, the server answers in the name of the missing one. 
+                let mut error = [0u8; HEADER_LENGTH + CONTROL_LENGTH]; 
+                write_error(&mut error, *from_ip.ip(), mysocket::UNREACHABLE, mysocket::NO_ROUTER, target_addr, 0); 
+                sender.send_to(&error, from_ip).await.unwrap(); 
+            }
             let p = format!("no router exists (ip={from_ip})"); 
             drop_packet(message_length, &p, buffer).await; 
             return ; 
         },
-    }
-    drop(global_router); 
-    let target_addr = SocketAddrV4::new(Ipv4Addr::new(buffer[0], buffer[1], buffer[2], buffer[3]), 
-        buffer[4] as u16 + (( buffer[5] as u16 ) << 8)); 
+    };
+    let priority = options.priority
+        .or_else(|| config::PORT_CLASSES.lock().unwrap().get(&target_addr.port()).copied())
+        .unwrap_or(0); 
+    let ecn = match options {
+        SendOptions { congestion_experienced: true, .. } => Ecn::Congested, 
+        SendOptions { ecn_capable: true, .. } => Ecn::Capable, 
+        _ => Ecn::NotCapable, 
+    }; 
+    let wait = match r.police(*from_ip.ip(), message_length - HEADER_LENGTH).await {
+        Ok(wait) => wait, 
+        Err(hint) => {
+            drop_packet(message_length, &hint, buffer).await; 
+            return 
+        }, 
+    }; 
     let src_ip = from_ip.ip().octets();
     for i in 0..4 {
         buffer[i] = src_ip[i]; 
     }
     buffer[4] = from_ip.port() as u8; 
     buffer[5] = (from_ip.port() >> 8) as u8; 
-    let message: Message = Message { target: target_addr, message: buffer, message_len: message_length }; 
+    let message: Message = Message { target: target_addr, message: buffer, message_len: message_length, kind: MessageKind::Data, hops: 0, enqueued: Duration::ZERO, priority, ecn, fragment: None, 
+        hop_limit: options.hop_limit.map_or(usize::MAX, usize::from).min(config::MAX_HOPS.load(Ordering::Relaxed)) }; 
     if cfg!(feature = "log-packet") {
         eprintln!("\x1b[32;1m[{:21}] packet forward and would be sent to {}\x1b[0m", "Packet Forward", target_addr); 
     }
-    r.sender().send(message).unwrap();
+    if sim::enabled() {
+        sim::inject(r.ipv4addr(), message, wait); 
+    } else {
+        if !wait.is_zero() {
+            sleep(wait).await; 
+        }
+        r.sender().send(message).unwrap();
+    }
 }
\ No newline at end of file
@@ -0,0 +1,74 @@
+use std::{env, net::{Ipv4Addr, SocketAddr, UdpSocket}, time::{Duration, Instant}};
+
+use our_game::mysocket::{self, MySocket, Received, SendOptions};
+
+const TIMEOUT: Duration = Duration::from_secs(1); 
+
+fn usage() -> ! {
+    eprintln!("usage: traceroute <local addr> <target addr> [max hops]"); 
+    std::process::exit(1)
+}
+
+/// what came back for one probe. 
+enum Answer {
+    /// a router on the path dropped it for the hop limit. 
+    Hop(Ipv4Addr), 
+    /// the target (a `ping --echo` there) answered. 
+    Reached(SocketAddr), 
+    /// a router could not forward it at all. 
+    Failed(Ipv4Addr, &'static str), 
+}
+
+fn probe(socket: &UdpSocket, target: SocketAddr, ttl: u8) -> Option<(Answer, Duration)> {
+    let start = Instant::now(); 
+    let options = SendOptions { hop_limit: Some(ttl), ..SendOptions::default() }; 
+    MySocket.send_with(socket, target, &[ttl], &options).ok()?; 
+    let mut contents = [0u8; 2500]; 
+    loop {
+        let left = TIMEOUT.saturating_sub(start.elapsed()); 
+        if left.is_zero() {
+            return None
+        }
+        socket.set_read_timeout(Some(left)).unwrap(); 
+        let answer = match MySocket.recv_message(socket, &mut contents) {
+            Some(Received::Error { from, kind: mysocket::TIME_EXCEEDED, target: t, .. }) if SocketAddr::V4(t) == target => 
+                Answer::Hop(from), 
+            Some(Received::Error { from, kind, code, target: t, .. }) if SocketAddr::V4(t) == target => 
+                Answer::Failed(from, mysocket::error_name(kind, code)), 
+            Some(Received::Data(1, from)) if from == target && contents[0] == ttl => Answer::Reached(from), 
+            _ => continue, 
+        }; 
+        return Some((answer, start.elapsed()))
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect(); 
+    let (local, target, max) = match args.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+        [local, target] => (local, target, 30), 
+        [local, target, max] => (local, target, max.parse().unwrap_or_else(|_| usage())), 
+        _ => usage(), 
+    }; 
+    let socket = UdpSocket::bind(local).unwrap(); 
+    let target: SocketAddr = target.parse().unwrap_or_else(|_| usage()); 
+    println!("traceroute to {target}, {max} hops max"); 
+    for ttl in 1..=max {
+        match probe(&socket, target, ttl) {
+            None => println!("{ttl:2}  *"), 
+            Some((answer, rtt)) => {
+                let ms = rtt.as_secs_f64() * 1000.; 
+                match answer {
+                    Answer::Hop(from) => println!("{ttl:2}  {from}  {ms:.3} ms"), 
+                    Answer::Reached(from) => {
+                        println!("{ttl:2}  {from}  {ms:.3} ms"); 
+                        return 
+                    }, 
+                    Answer::Failed(from, why) => {
+                        println!("{ttl:2}  {from}  {ms:.3} ms !{why}"); 
+                        return 
+                    }, 
+                }
+            }, 
+        }
+    }
+}
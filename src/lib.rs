@@ -1,2 +1,6 @@
 pub mod router; 
-pub mod mysocket; 
\ No newline at end of file
+pub mod mysocket; 
+pub mod sim; 
+pub mod routing; 
+pub mod queue; 
+pub mod shaper; 
\ No newline at end of file
@@ -2,11 +2,127 @@ use std::net::{UdpSocket, ToSocketAddrs, SocketAddr, SocketAddrV4, Ipv4Addr};
 
 pub struct MySocket; 
 
+/// the port in the header of a control message, no client sends from it. 
+pub const CONTROL_PORT: u16 = 0; 
+/// the bytes of a control message behind the header: type, code, ipv4 and port of the target, and 
+/// the mtu (u16) of `FRAG_NEEDED`, zero for the others. 
+pub const CONTROL_LENGTH: usize = 10; 
+
+/// types of the control messages, numbered after ICMP. 
+pub const UNREACHABLE: u8 = 3; 
+pub const TIME_EXCEEDED: u8 = 11; 
+
+/// codes of `UNREACHABLE`. 
+pub const NO_ROUTE: u8 = 0; 
+pub const NO_ROUTER: u8 = 1; 
+/// the packet is larger than the mtu of the next link, and the routers do not fragment. 
+pub const FRAG_NEEDED: u8 = 4; 
+
+/// codes of `TIME_EXCEEDED`. 
+pub const HOP_LIMIT: u8 = 0; 
+/// the fragments of the packet did not all reach the target router in time. 
+pub const REASSEMBLY: u8 = 1; 
+
+/// a readable name of a control message type and code. 
+pub fn error_name(kind: u8, code: u8) -> &'static str {
+    match (kind, code) {
+        (UNREACHABLE, NO_ROUTE) => "no route to the target", 
+        (UNREACHABLE, NO_ROUTER) => "no router for the source", 
+        (UNREACHABLE, FRAG_NEEDED) => "fragmentation needed", 
+        (UNREACHABLE, _) => "target unreachable", 
+        (TIME_EXCEEDED, REASSEMBLY) => "fragment reassembly time exceeded", 
+        (TIME_EXCEEDED, _) => "hop limit exceeded", 
+        _ => "unknown error", 
+    }
+}
+
+/// the first bytes of an extended header, where the target of a plain header would be. 0.0.0.0 is 
+/// never a target, the options length (u16) follows, then the target and the options. 
+pub const EXTENDED_MARK: [u8; 4] = [0; 4]; 
+
+/// option kinds of the extended header, each option is a kind byte and a value byte. 
+pub const OPTION_HOP_LIMIT: u8 = 1; 
+pub const OPTION_PRIORITY: u8 = 2; 
+pub const OPTION_ECN: u8 = 3; 
+
+/// bits of the `OPTION_ECN` value. 
+pub const ECN_CAPABLE: u8 = 1; 
+pub const ECN_CONGESTION: u8 = 2; 
+
+/// options of a packet, the emulator uses its own defaults for the unset ones. the emulator 
+/// delivers a packet with an extended header too, when a router marked it. 
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SendOptions {
+    /// the routers the packet may pass, see `TIME_EXCEEDED`. 
+    pub hop_limit: Option<u8>, 
+    /// the class of the packet in the priority and fair queueing schedulers, the higher the more urgent. 
+    pub priority: Option<u8>, 
+    /// the sender understands ECN, the routers may mark the packet instead of dropping it. 
+    pub ecn_capable: bool, 
+    /// a router on the path marked the packet, set on the received ones. 
+    pub congestion_experienced: bool, 
+}
+
+impl SendOptions {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::new(); 
+        if let Some(h) = self.hop_limit {
+            bytes.extend_from_slice(&[OPTION_HOP_LIMIT, h]); 
+        }
+        if let Some(p) = self.priority {
+            bytes.extend_from_slice(&[OPTION_PRIORITY, p]); 
+        }
+        if self.ecn_capable || self.congestion_experienced {
+            let flags = if self.ecn_capable { ECN_CAPABLE } else { 0 } | if self.congestion_experienced { ECN_CONGESTION } else { 0 }; 
+            bytes.extend_from_slice(&[OPTION_ECN, flags]); 
+        }
+        bytes
+    }
+
+    /// the options of an extended header, the unknown kinds are skipped. 
+    pub fn decode(bytes: &[u8]) -> SendOptions {
+        let mut options = SendOptions::default(); 
+        for option in bytes.chunks_exact(2) {
+            match option[0] {
+                OPTION_HOP_LIMIT => options.hop_limit = Some(option[1]), 
+                OPTION_PRIORITY => options.priority = Some(option[1]), 
+                OPTION_ECN => {
+                    options.ecn_capable = option[1] & ECN_CAPABLE != 0; 
+                    options.congestion_experienced = option[1] & ECN_CONGESTION != 0; 
+                }, 
+                _ => {}, 
+            }
+        }
+        options
+    }
+}
+
+/// what `MySocket::recv_message` got from the emulator. 
+#[derive(Debug, Clone, Copy)]
+pub enum Received {
+    /// `len` bytes of payload from a client. 
+    Data(usize, SocketAddr), 
+    /// the router `from` dropped a packet sent to `target`, `mtu` is the one of the link it did not 
+    /// fit with `FRAG_NEEDED`. 
+    Error { from: Ipv4Addr, kind: u8, code: u8, target: SocketAddrV4, mtu: u16 }, 
+}
+
 #[allow(unused)]
 impl MySocket {
+    #[allow(clippy::result_unit_err)]
     pub fn send (&self, proxy: &UdpSocket, send_to: impl ToSocketAddrs, content: &[u8]) -> Result<(), ()> {
-        let mut new_contents = Vec::new(); 
-        new_contents.reserve(content.len() + 6); 
+        self.send_with(proxy, send_to, content, &SendOptions::default())
+    }
+
+    /// like `send`, the options go into an extended header when any of them is set. 
+    #[allow(clippy::result_unit_err)]
+    pub fn send_with(&self, proxy: &UdpSocket, send_to: impl ToSocketAddrs, content: &[u8], options: &SendOptions) -> Result<(), ()> {
+        let options = options.encode(); 
+        let mut new_contents = Vec::with_capacity(content.len() + 12 + options.len()); 
+        if !options.is_empty() {
+            new_contents.extend_from_slice(&EXTENDED_MARK); 
+            new_contents.extend_from_slice(&(options.len() as u16).to_le_bytes()); 
+        }
         match send_to.to_socket_addrs() {
             Ok(mut o) => {
                 let o = o.next(); 
@@ -33,6 +149,7 @@ impl MySocket {
                 return Err(())
             },
         }
+        new_contents.extend_from_slice(&options); 
         new_contents.extend_from_slice(content);
         match proxy.send_to(&new_contents, "127.67.117.116:52736") {
             Ok(c) => {
@@ -47,19 +164,65 @@ impl MySocket {
     }
 
     pub fn recv(&self, proxy: &UdpSocket, content: &mut [u8]) -> Option<(usize, SocketAddr)> {
+        self.recv_with_options(proxy, content).map(|(len, from, _)| (len, from))
+    }
+
+    /// like `recv`, with the options of an extended header, such as the ECN mark. 
+    pub fn recv_with_options(&self, proxy: &UdpSocket, content: &mut [u8]) -> Option<(usize, SocketAddr, SendOptions)> {
         let r = proxy.recv_from(content); 
         match r {
             Ok((len, _)) => {
                 if len < 6 {
                     return None
                 }
+                let mut header = 6; 
+                let mut options = SendOptions::default(); 
+                if content[..4] == EXTENDED_MARK {
+                    header = 12 + content[4] as usize + content[5] as usize * 0x100; 
+                    if len < header {
+                        return None
+                    }
+                    options = SendOptions::decode(&content[12..header]); 
+                    content.copy_within(6..12, 0); 
+                }
                 let sock_addr = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(content[0], content[1], content[2], content[3]), content[4] as u16 + content[5] as u16 * 0x100)); 
-                for index in 0..(len-6) {
-                    content[index] = content[index+6]; 
+                for index in 0..(len-header) {
+                    content[index] = content[index+header]; 
                 }
-                return Some((len - 6, sock_addr))
+                Some((len - header, sock_addr, options))
             },
-            Err(_) => { return None },
+            Err(_) => { None },
         }
     }
-}
\ No newline at end of file
+
+    /// like `recv`, but tells the control messages of the routers apart from the data. 
+    pub fn recv_message(&self, proxy: &UdpSocket, content: &mut [u8]) -> Option<Received> {
+        let (len, from) = self.recv(proxy, content)?; 
+        match from {
+            SocketAddr::V4(from) if from.port() == CONTROL_PORT && len >= CONTROL_LENGTH => {
+                let c = &content[..CONTROL_LENGTH]; 
+                let target = SocketAddrV4::new(Ipv4Addr::new(c[2], c[3], c[4], c[5]), c[6] as u16 + c[7] as u16 * 0x100); 
+                Some(Received::Error { from: *from.ip(), kind: c[0], code: c[1], target, mtu: c[8] as u16 + c[9] as u16 * 0x100 })
+            },
+            _ => Some(Received::Data(len, from)), 
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*; 
+
+    #[test]
+    fn send_options_round_trip() {
+        let options = SendOptions { hop_limit: Some(3), priority: Some(7), ecn_capable: true, congestion_experienced: true }; 
+        assert_eq!(SendOptions::decode(&options.encode()), options); 
+        assert!(SendOptions::default().encode().is_empty()); 
+    }
+
+    #[test]
+    fn send_options_skip_unknown_kinds() {
+        let options = SendOptions::decode(&[200, 1, OPTION_HOP_LIMIT, 9, OPTION_ECN, ECN_CAPABLE]); 
+        assert_eq!(options, SendOptions { hop_limit: Some(9), ecn_capable: true, ..SendOptions::default() }); 
+    }
+}
@@ -0,0 +1,896 @@
+//! Queue disciplines of the link output queues: which packets to take in, drop, and send next.
+
+use std::{collections::{hash_map::DefaultHasher, LinkedList, VecDeque}, fmt, hash::{Hash, Hasher}, time::Duration}; 
+
+use rand::{rngs::StdRng, Rng}; 
+
+use crate::router::Message; 
+
+/// How much a queue holds: packets, bytes (the address header included), or whichever is reached first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueueLimit {
+    pub packets: Option<usize>, 
+    pub bytes: Option<usize>, 
+}
+
+impl QueueLimit {
+    pub const fn packets(packets: usize) -> QueueLimit {
+        QueueLimit { packets: Some(packets), bytes: None }
+    }
+
+    /// whether a packet of `len` bytes fits next to `packets` packets of `bytes` bytes.
+    pub fn admits(&self, packets: usize, bytes: usize, len: usize) -> bool {
+        self.packets.is_none_or(|p| packets < p) && self.bytes.is_none_or(|b| bytes + len <= b)
+    }
+}
+
+impl fmt::Display for QueueLimit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.packets, self.bytes) {
+            (Some(p), Some(b)) => write!(f, "{p} packets or {b} bytes"), 
+            (Some(p), None) => write!(f, "{p} packets"), 
+            (None, Some(b)) => write!(f, "{b} bytes"), 
+            (None, None) => write!(f, "unlimited"), 
+        }
+    }
+}
+
+/// A FIFO of packets that keeps count of their bytes.
+#[derive(Default)]
+pub struct Fifo {
+    packets: LinkedList<Message>, 
+    bytes: usize, 
+}
+
+impl Fifo {
+    pub fn push_back(&mut self, m: Message) {
+        self.bytes += m.message_len; 
+        self.packets.push_back(m); 
+    }
+
+    pub fn pop_front(&mut self) -> Option<Message> {
+        let m = self.packets.pop_front()?; 
+        self.bytes -= m.message_len; 
+        Some(m)
+    }
+
+    pub fn front(&self) -> Option<&Message> {
+        self.packets.front()
+    }
+
+    pub fn len(&self) -> usize {
+        self.packets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.packets.is_empty()
+    }
+
+    pub const fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    /// take all the packets out, the oldest first.
+    pub fn drain(&mut self) -> Vec<Message> {
+        self.bytes = 0; 
+        std::mem::take(&mut self.packets).into_iter().collect()
+    }
+
+    /// whether `m` fits in the queue under `limit`.
+    pub fn admits(&self, limit: &QueueLimit, m: &Message) -> bool {
+        limit.admits(self.len(), self.bytes, m.message_len)
+    }
+}
+
+/// The output queue of one link.
+pub trait QueueDiscipline: Send + Sync {
+    /// take the packet in at `now`, or give back a packet (this one, or one queued before) with the 
+    /// reason to drop it.
+    fn enqueue(&mut self, m: Message, now: Duration, rng: &mut StdRng) -> Result<(), (Message, String)>; 
+
+    /// the next packet for the transmitter, the packets dropped on the way go into `dropped`.
+    fn dequeue(&mut self, now: Duration, dropped: &mut Vec<(Message, String)>) -> Option<Message>; 
+
+    /// take every queued packet out as it is, without the drops and marks of the discipline.
+    fn drain(&mut self) -> Vec<Message>; 
+
+    fn len(&self) -> usize; 
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// the bytes of the queued packets.
+    fn bytes(&self) -> usize; 
+
+    fn limit(&self) -> QueueLimit; 
+
+    fn set_limit(&mut self, limit: QueueLimit); 
+
+    /// the name and the counters of the discipline, for the STATS command.
+    fn stats(&self) -> String; 
+}
+
+/// Take packets in until the queue is full, and drop the arriving ones then.
+pub struct DropTail {
+    queue: Fifo, 
+    limit: QueueLimit, 
+    overflows: usize, 
+}
+
+impl DropTail {
+    pub fn new(limit: QueueLimit) -> DropTail {
+        DropTail { queue: Fifo::default(), limit, overflows: 0 }
+    }
+}
+
+impl QueueDiscipline for DropTail {
+    fn enqueue(&mut self, m: Message, _: Duration, _: &mut StdRng) -> Result<(), (Message, String)> {
+        if self.queue.admits(&self.limit, &m) {
+            self.queue.push_back(m); 
+            Ok(())
+        } else {
+            self.overflows += 1; 
+            Err((m, "queue buffer overflow".to_string()))
+        }
+    }
+
+    fn dequeue(&mut self, _: Duration, _: &mut Vec<(Message, String)>) -> Option<Message> {
+        self.queue.pop_front()
+    }
+
+    fn drain(&mut self) -> Vec<Message> {
+        self.queue.drain()
+    }
+
+    fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    fn bytes(&self) -> usize {
+        self.queue.bytes()
+    }
+
+    fn limit(&self) -> QueueLimit {
+        self.limit
+    }
+
+    fn set_limit(&mut self, limit: QueueLimit) {
+        self.limit = limit; 
+    }
+
+    fn stats(&self) -> String {
+        format!("drop-tail, overflows {}", self.overflows)
+    }
+}
+
+/// The parameters of RED, the thresholds are in packets of the average queue length.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RedConfig {
+    pub min_threshold: f64, 
+    pub max_threshold: f64, 
+    /// the drop probability when the average reaches `max_threshold`.
+    pub max_p: f64, 
+    /// the weight of the current length in the moving average.
+    pub weight: f64, 
+    /// mark the ECN-capable packets instead of the early drops.
+    pub ecn: bool, 
+}
+
+/// Random Early Detection of Floyd and Jacobson: drop at random once the average queue length passes
+/// the minimum threshold, and always once it passes the maximum one.
+pub struct Red {
+    queue: Fifo, 
+    limit: QueueLimit, 
+    config: RedConfig, 
+    average: f64, 
+    /// packets taken in since the last drop while the average is between the thresholds, or -1.
+    count: i64, 
+    /// drops at random between the thresholds.
+    early_drops: usize, 
+    /// ECN-capable packets marked instead of an early drop.
+    marks: usize, 
+    /// drops above the maximum threshold or at the limit.
+    forced_drops: usize, 
+}
+
+impl Red {
+    pub fn new(limit: QueueLimit, config: RedConfig) -> Red {
+        Red { queue: Fifo::default(), limit, config, average: 0., count: -1, early_drops: 0, marks: 0, forced_drops: 0 }
+    }
+
+    fn forced(&mut self, m: Message) -> Result<(), (Message, String)> {
+        self.count = 0; 
+        self.forced_drops += 1; 
+        Err((m, format!("RED forced drop (avg {:.2})", self.average)))
+    }
+}
+
+impl QueueDiscipline for Red {
+    fn enqueue(&mut self, mut m: Message, _: Duration, rng: &mut StdRng) -> Result<(), (Message, String)> {
+        let RedConfig { min_threshold, max_threshold, max_p, weight, ecn } = self.config; 
+        self.average = (1. - weight) * self.average + weight * self.queue.len() as f64; 
+        if !self.queue.admits(&self.limit, &m) || self.average >= max_threshold {
+            return self.forced(m)
+        }
+        if self.average >= min_threshold {
+            self.count += 1; 
+            let pb = max_p * (self.average - min_threshold) / (max_threshold - min_threshold); 
+            // spread the drops evenly, the probability grows with the packets since the last drop.
+            let pa = if self.count as f64 * pb >= 1. { 1. } else { pb / (1. - self.count as f64 * pb) }; 
+            if rng.gen_bool(pa.clamp(0., 1.)) {
+                self.count = 0; 
+                if ecn && m.ecn.mark() {
+                    self.marks += 1; 
+                } else {
+                    self.early_drops += 1; 
+                    return Err((m, format!("RED early drop (avg {:.2})", self.average)))
+                }
+            }
+        } else {
+            self.count = -1; 
+        }
+        self.queue.push_back(m); 
+        Ok(())
+    }
+
+    fn dequeue(&mut self, _: Duration, _: &mut Vec<(Message, String)>) -> Option<Message> {
+        self.queue.pop_front()
+    }
+
+    fn drain(&mut self) -> Vec<Message> {
+        self.queue.drain()
+    }
+
+    fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    fn bytes(&self) -> usize {
+        self.queue.bytes()
+    }
+
+    fn limit(&self) -> QueueLimit {
+        self.limit
+    }
+
+    fn set_limit(&mut self, limit: QueueLimit) {
+        self.limit = limit; 
+    }
+
+    fn stats(&self) -> String {
+        format!("RED, avg {:.2}, early drops {}, marks {}, forced drops {}", self.average, self.early_drops, self.marks, self.forced_drops)
+    }
+}
+
+/// The parameters of CoDel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CodelConfig {
+    /// the sojourn time the queue is allowed to keep.
+    pub target: Duration, 
+    /// how long the sojourn time may stay above `target` before dropping starts.
+    pub interval: Duration, 
+    /// mark the ECN-capable packets instead of dropping them.
+    pub ecn: bool, 
+}
+
+/// the longest `target` and `interval`, the state machine adds up multiples of the interval.
+pub const CODEL_MAX_TIME: Duration = Duration::from_secs(60); 
+
+/// the largest quantum of FQ-CoDel, in bytes.
+pub const FQ_MAX_QUANTUM: usize = 65536; 
+
+impl Default for CodelConfig {
+    fn default() -> CodelConfig {
+        CodelConfig { target: Duration::from_millis(5), interval: Duration::from_millis(100), ecn: false }
+    }
+}
+
+/// The control state of CoDel over one queue, after RFC 8289.
+#[derive(Debug, Default)]
+struct CodelState {
+    /// when the sojourn time went above the target plus an interval, if it is above.
+    first_above_time: Option<Duration>, 
+    dropping: bool, 
+    drop_next: Duration, 
+    count: u32, 
+    last_count: u32, 
+    drops: usize, 
+    marks: usize, 
+}
+
+impl CodelState {
+    fn control_law(t: Duration, count: u32, config: &CodelConfig) -> Duration {
+        t + config.interval.div_f64((count.max(1) as f64).sqrt())
+    }
+
+    /// pop the head, and tell whether the sojourn time has stayed above the target for an interval.
+    fn pop(&mut self, queue: &mut Fifo, now: Duration, config: &CodelConfig) -> (Option<Message>, bool) {
+        let Some(m) = queue.pop_front() else {
+            self.first_above_time = None; 
+            return (None, false)
+        }; 
+        let sojourn = now.saturating_sub(m.enqueued); 
+        let mut ok_to_drop = false; 
+        if sojourn < config.target || queue.is_empty() {
+            self.first_above_time = None; 
+        } else if let Some(first) = self.first_above_time {
+            ok_to_drop = now >= first; 
+        } else {
+            self.first_above_time = Some(now + config.interval); 
+        }
+        (Some(m), ok_to_drop)
+    }
+
+    fn mark(&mut self, m: &mut Message) -> bool {
+        let marked = m.ecn.mark(); 
+        if marked {
+            self.marks += 1; 
+        }
+        marked
+    }
+
+    fn drop(&mut self, m: Message, now: Duration, dropped: &mut Vec<(Message, String)>) {
+        self.drops += 1; 
+        let sojourn = now.saturating_sub(m.enqueued); 
+        dropped.push((m, format!("CoDel drop (sojourn {:.1} ms)", sojourn.as_secs_f64() * 1000.))); 
+    }
+
+    fn dequeue(&mut self, queue: &mut Fifo, now: Duration, config: &CodelConfig, dropped: &mut Vec<(Message, String)>) -> Option<Message> {
+        let (mut m, ok_to_drop) = self.pop(queue, now, config); 
+        if m.is_none() {
+            self.dropping = false; 
+            return None
+        }
+        if self.dropping {
+            if !ok_to_drop {
+                self.dropping = false; 
+            }
+            while self.dropping && now >= self.drop_next {
+                self.count += 1; 
+                if config.ecn && self.mark(m.as_mut().unwrap()) {
+                    // a marked packet goes on, the next signal is due by the control law.
+                    self.drop_next = CodelState::control_law(self.drop_next, self.count, config); 
+                    break 
+                }
+                self.drop(m.take().unwrap(), now, dropped); 
+                let (next, ok_to_drop) = self.pop(queue, now, config); 
+                m = next; 
+                if !ok_to_drop || m.is_none() {
+                    self.dropping = false; 
+                } else {
+                    self.drop_next = CodelState::control_law(self.drop_next, self.count, config); 
+                }
+            }
+        } else if ok_to_drop {
+            if !(config.ecn && self.mark(m.as_mut().unwrap())) {
+                self.drop(m.take().unwrap(), now, dropped); 
+                m = self.pop(queue, now, config).0; 
+            }
+            self.dropping = true; 
+            // start near the drop rate of the last dropping state if it ended recently.
+            let delta = self.count.saturating_sub(self.last_count); 
+            self.count = if delta > 1 && now.saturating_sub(self.drop_next) < config.interval * 16 { delta } else { 1 }; 
+            self.drop_next = CodelState::control_law(now, self.count, config); 
+            self.last_count = self.count; 
+        }
+        m
+    }
+}
+
+/// Controlled Delay: drop at the head once the packets have waited more than the target for an 
+/// interval, more often while it lasts.
+pub struct Codel {
+    queue: Fifo, 
+    limit: QueueLimit, 
+    config: CodelConfig, 
+    state: CodelState, 
+    overflows: usize, 
+}
+
+impl Codel {
+    pub fn new(limit: QueueLimit, config: CodelConfig) -> Codel {
+        Codel { queue: Fifo::default(), limit, config, state: CodelState::default(), overflows: 0 }
+    }
+}
+
+impl QueueDiscipline for Codel {
+    fn enqueue(&mut self, m: Message, _: Duration, _: &mut StdRng) -> Result<(), (Message, String)> {
+        if self.queue.admits(&self.limit, &m) {
+            self.queue.push_back(m); 
+            Ok(())
+        } else {
+            self.overflows += 1; 
+            Err((m, "queue buffer overflow".to_string()))
+        }
+    }
+
+    fn dequeue(&mut self, now: Duration, dropped: &mut Vec<(Message, String)>) -> Option<Message> {
+        self.state.dequeue(&mut self.queue, now, &self.config, dropped)
+    }
+
+    fn drain(&mut self) -> Vec<Message> {
+        self.queue.drain()
+    }
+
+    fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    fn bytes(&self) -> usize {
+        self.queue.bytes()
+    }
+
+    fn limit(&self) -> QueueLimit {
+        self.limit
+    }
+
+    fn set_limit(&mut self, limit: QueueLimit) {
+        self.limit = limit; 
+    }
+
+    fn stats(&self) -> String {
+        format!("CoDel, drops {}, marks {}, overflows {}", self.state.drops, self.state.marks, self.overflows)
+    }
+}
+
+/// the sub-queues of FQ-CoDel, flows share them by hash.
+const FLOWS: usize = 1024; 
+
+#[derive(Default)]
+struct Flow {
+    queue: Fifo, 
+    state: CodelState, 
+    /// bytes the flow may still send in this round.
+    deficit: i64, 
+    /// whether the flow is in the new or the old list.
+    active: bool, 
+}
+
+/// Flow-queueing CoDel, after RFC 8290: the flows hashed by (source, target) get a CoDel queue each, 
+/// and a deficit round robin between them, the new flows first.
+pub struct FqCodel {
+    flows: Vec<Flow>, 
+    new_flows: VecDeque<usize>, 
+    old_flows: VecDeque<usize>, 
+    len: usize, 
+    bytes: usize, 
+    limit: QueueLimit, 
+    config: CodelConfig, 
+    /// bytes a flow sends per round.
+    quantum: usize, 
+    overflows: usize, 
+}
+
+impl FqCodel {
+    pub fn new(limit: QueueLimit, config: CodelConfig, quantum: usize) -> FqCodel {
+        FqCodel { flows: (0..FLOWS).map(|_| Flow::default()).collect(), new_flows: VecDeque::new(), old_flows: VecDeque::new(), 
+            len: 0, bytes: 0, limit, config, quantum, overflows: 0 }
+    }
+
+    fn flow_of(m: &Message) -> usize {
+        let mut hasher = DefaultHasher::new(); 
+        (m.source(), m.target).hash(&mut hasher); 
+        hasher.finish() as usize % FLOWS
+    }
+}
+
+impl QueueDiscipline for FqCodel {
+    fn enqueue(&mut self, m: Message, _: Duration, _: &mut StdRng) -> Result<(), (Message, String)> {
+        let i = FqCodel::flow_of(&m); 
+        let flow = &mut self.flows[i]; 
+        if !flow.active {
+            flow.active = true; 
+            flow.deficit = self.quantum as i64; 
+            self.new_flows.push_back(i); 
+        }
+        let fits = self.limit.admits(self.len, self.bytes, m.message_len); 
+        self.len += 1; 
+        self.bytes += m.message_len; 
+        flow.queue.push_back(m); 
+        if fits {
+            return Ok(())
+        }
+        // over the limit, the head of the flow with the most bytes goes.
+        let fattest = self.flows.iter_mut().max_by_key(|f| f.queue.bytes()).unwrap(); 
+        let head = fattest.queue.pop_front().unwrap(); 
+        self.len -= 1; 
+        self.bytes -= head.message_len; 
+        self.overflows += 1; 
+        Err((head, "FQ-CoDel overflow of the fattest flow".to_string()))
+    }
+
+    fn dequeue(&mut self, now: Duration, dropped: &mut Vec<(Message, String)>) -> Option<Message> {
+        loop {
+            let (i, new) = match (self.new_flows.front(), self.old_flows.front()) {
+                (Some(&i), _) => (i, true), 
+                (None, Some(&i)) => (i, false), 
+                (None, None) => return None, 
+            }; 
+            let list = if new { &mut self.new_flows } else { &mut self.old_flows }; 
+            let flow = &mut self.flows[i]; 
+            if flow.deficit <= 0 {
+                flow.deficit += self.quantum as i64; 
+                list.pop_front(); 
+                self.old_flows.push_back(i); 
+                continue 
+            }
+            let before = dropped.len(); 
+            let m = flow.state.dequeue(&mut flow.queue, now, &self.config, dropped); 
+            self.len -= dropped.len() - before; 
+            self.bytes -= dropped[before..].iter().map(|(d, _)| d.message_len).sum::<usize>(); 
+            match m {
+                Some(m) => {
+                    flow.deficit -= m.message_len as i64; 
+                    self.len -= 1; 
+                    self.bytes -= m.message_len; 
+                    return Some(m)
+                }, 
+                None => {
+                    list.pop_front(); 
+                    // an emptied new flow goes behind the old ones, so it cannot keep the front.
+                    if new && !self.old_flows.is_empty() {
+                        self.old_flows.push_back(i); 
+                    } else {
+                        flow.active = false; 
+                    }
+                }, 
+            }
+        }
+    }
+
+    fn drain(&mut self) -> Vec<Message> {
+        self.new_flows.clear(); 
+        self.old_flows.clear(); 
+        self.len = 0; 
+        self.bytes = 0; 
+        self.flows.iter_mut().flat_map(|f| {
+            f.active = false; 
+            f.queue.drain()
+        }).collect()
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    fn limit(&self) -> QueueLimit {
+        self.limit
+    }
+
+    fn set_limit(&mut self, limit: QueueLimit) {
+        self.limit = limit; 
+    }
+
+    fn stats(&self) -> String {
+        let drops: usize = self.flows.iter().map(|f| f.state.drops).sum(); 
+        let marks: usize = self.flows.iter().map(|f| f.state.marks).sum(); 
+        format!("FQ-CoDel, {} active flows, drops {drops}, marks {marks}, overflows {}", self.new_flows.len() + self.old_flows.len(), self.overflows)
+    }
+}
+
+/// Per-class queues sharing one limit, the class of a packet is its priority, the last class takes 
+/// the higher ones.
+struct Classes {
+    queues: Vec<Fifo>, 
+    len: usize, 
+    bytes: usize, 
+    limit: QueueLimit, 
+    sent: Vec<usize>, 
+    overflows: Vec<usize>, 
+}
+
+impl Classes {
+    fn new(classes: usize, limit: QueueLimit) -> Classes {
+        Classes { queues: (0..classes).map(|_| Fifo::default()).collect(), len: 0, bytes: 0, limit, sent: vec![0; classes], overflows: vec![0; classes] }
+    }
+
+    fn enqueue(&mut self, m: Message) -> Result<(), (Message, String)> {
+        let class = (m.priority as usize).min(self.queues.len() - 1); 
+        if !self.limit.admits(self.len, self.bytes, m.message_len) {
+            self.overflows[class] += 1; 
+            return Err((m, format!("queue buffer overflow (class {class})")))
+        }
+        self.len += 1; 
+        self.bytes += m.message_len; 
+        self.queues[class].push_back(m); 
+        Ok(())
+    }
+
+    fn pop(&mut self, class: usize) -> Option<Message> {
+        let m = self.queues[class].pop_front()?; 
+        self.len -= 1; 
+        self.bytes -= m.message_len; 
+        self.sent[class] += 1; 
+        Some(m)
+    }
+
+    /// the packets of every class, the most urgent first.
+    fn drain(&mut self) -> Vec<Message> {
+        self.len = 0; 
+        self.bytes = 0; 
+        self.queues.iter_mut().rev().flat_map(Fifo::drain).collect()
+    }
+
+    fn stats(&self) -> String {
+        let classes: Vec<String> = (0..self.queues.len())
+            .map(|c| format!("class {c}: {} queued, {} sent, {} overflows", self.queues[c].len(), self.sent[c], self.overflows[c]))
+            .collect(); 
+        classes.join("; ")
+    }
+}
+
+/// Strict priority: always send from the most urgent class with a packet waiting.
+pub struct Priority {
+    classes: Classes, 
+}
+
+impl Priority {
+    pub fn new(limit: QueueLimit, classes: usize) -> Priority {
+        Priority { classes: Classes::new(classes.max(1), limit) }
+    }
+}
+
+impl QueueDiscipline for Priority {
+    fn enqueue(&mut self, m: Message, _: Duration, _: &mut StdRng) -> Result<(), (Message, String)> {
+        self.classes.enqueue(m)
+    }
+
+    fn dequeue(&mut self, _: Duration, _: &mut Vec<(Message, String)>) -> Option<Message> {
+        let class = (0..self.classes.queues.len()).rev().find(|c| !self.classes.queues[*c].is_empty())?; 
+        self.classes.pop(class)
+    }
+
+    fn drain(&mut self) -> Vec<Message> {
+        self.classes.drain()
+    }
+
+    fn len(&self) -> usize {
+        self.classes.len
+    }
+
+    fn bytes(&self) -> usize {
+        self.classes.bytes
+    }
+
+    fn limit(&self) -> QueueLimit {
+        self.classes.limit
+    }
+
+    fn set_limit(&mut self, limit: QueueLimit) {
+        self.classes.limit = limit; 
+    }
+
+    fn stats(&self) -> String {
+        format!("strict priority, {}", self.classes.stats())
+    }
+}
+
+/// the bytes a class of weight 1 sends per round of `Drr`.
+pub const DRR_QUANTUM: usize = 1514; 
+
+/// the largest weight of a class, so its quantum stays far below `usize::MAX`.
+pub const DRR_MAX_WEIGHT: usize = 1000; 
+
+/// Deficit round robin between the classes, each sends its weight times `DRR_QUANTUM` bytes per 
+/// round, which shares the link by the weights like weighted fair queueing.
+pub struct Drr {
+    classes: Classes, 
+    weights: Vec<usize>, 
+    deficits: Vec<usize>, 
+    /// the class whose turn it is.
+    current: usize, 
+}
+
+impl Drr {
+    /// the weights are taken into [1, `DRR_MAX_WEIGHT`].
+    pub fn new(limit: QueueLimit, weights: Vec<usize>) -> Drr {
+        let weights = if weights.is_empty() { vec![1] } else { weights.into_iter().map(|w| w.clamp(1, DRR_MAX_WEIGHT)).collect() }; 
+        Drr { classes: Classes::new(weights.len(), limit), deficits: vec![0; weights.len()], weights, current: 0 }
+    }
+}
+
+impl QueueDiscipline for Drr {
+    fn enqueue(&mut self, m: Message, _: Duration, _: &mut StdRng) -> Result<(), (Message, String)> {
+        self.classes.enqueue(m)
+    }
+
+    fn dequeue(&mut self, _: Duration, _: &mut Vec<(Message, String)>) -> Option<Message> {
+        if self.classes.len == 0 {
+            return None
+        }
+        loop {
+            let c = self.current; 
+            match self.classes.queues[c].front() {
+                Some(m) if m.message_len <= self.deficits[c] => {
+                    self.deficits[c] -= m.message_len; 
+                    return self.classes.pop(c)
+                }, 
+                Some(_) => {
+                    // not enough for the head, the class gets its quantum and waits for the next round.
+                    self.deficits[c] += self.weights[c] * DRR_QUANTUM; 
+                }, 
+                None => {
+                    // an idle class keeps no credit.
+                    self.deficits[c] = 0; 
+                }, 
+            }
+            self.current = (c + 1) % self.weights.len(); 
+        }
+    }
+
+    fn drain(&mut self) -> Vec<Message> {
+        self.deficits.fill(0); 
+        self.classes.drain()
+    }
+
+    fn len(&self) -> usize {
+        self.classes.len
+    }
+
+    fn bytes(&self) -> usize {
+        self.classes.bytes
+    }
+
+    fn limit(&self) -> QueueLimit {
+        self.classes.limit
+    }
+
+    fn set_limit(&mut self, limit: QueueLimit) {
+        self.classes.limit = limit; 
+    }
+
+    fn stats(&self) -> String {
+        format!("DRR with weights {:?}, {}", self.weights, self.classes.stats())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, SocketAddrV4}; 
+
+    use rand::SeedableRng; 
+
+    use crate::router::{Ecn, MessageKind, MESSAGE_LENGTH}; 
+
+    use super::*; 
+
+    const MS: Duration = Duration::from_millis(1); 
+
+    fn message(len: usize, priority: u8, ecn: Ecn, enqueued: Duration) -> Message {
+        Message { target: SocketAddrV4::new(Ipv4Addr::LOCALHOST, 9000), message: Box::new([0; MESSAGE_LENGTH]), message_len: len, 
+            kind: MessageKind::Data, hops: 0, hop_limit: 64, enqueued, priority, ecn, fragment: None }
+    }
+
+    fn red(ecn: bool) -> Red {
+        // the weight 1 makes the average the current length.
+        Red::new(QueueLimit::packets(1000), RedConfig { min_threshold: 2., max_threshold: 6., max_p: 0.1, weight: 1., ecn })
+    }
+
+    #[test]
+    fn red_drops_early_between_the_thresholds() {
+        let mut rng = StdRng::seed_from_u64(1); 
+        let mut q = red(false); 
+        while q.len() < 4 {
+            let _ = q.enqueue(message(100, 0, Ecn::NotCapable, Duration::ZERO), Duration::ZERO, &mut rng); 
+        }
+        // one in, one out: the average stays at 4, between the thresholds.
+        for _ in 0..1000 {
+            if q.enqueue(message(100, 0, Ecn::NotCapable, Duration::ZERO), Duration::ZERO, &mut rng).is_ok() {
+                q.dequeue(Duration::ZERO, &mut Vec::new()); 
+            }
+        }
+        assert!(q.early_drops > 10, "{}", q.stats()); 
+        assert_eq!(q.forced_drops, 0); 
+    }
+
+    #[test]
+    fn red_forces_drops_over_the_max_threshold() {
+        let mut rng = StdRng::seed_from_u64(1); 
+        let mut q = red(false); 
+        for _ in 0..200 {
+            let _ = q.enqueue(message(100, 0, Ecn::NotCapable, Duration::ZERO), Duration::ZERO, &mut rng); 
+        }
+        assert_eq!(q.len(), 6); 
+        assert_eq!(q.early_drops + q.forced_drops + q.len(), 200); 
+        assert!(q.forced_drops >= 190); 
+    }
+
+    #[test]
+    fn red_marks_instead_of_early_drops() {
+        let mut rng = StdRng::seed_from_u64(1); 
+        let mut q = red(true); 
+        for _ in 0..4 {
+            q.enqueue(message(100, 0, Ecn::Capable, Duration::ZERO), Duration::ZERO, &mut rng).unwrap(); 
+        }
+        for _ in 0..1000 {
+            q.enqueue(message(100, 0, Ecn::Capable, Duration::ZERO), Duration::ZERO, &mut rng).unwrap(); 
+            q.dequeue(Duration::ZERO, &mut Vec::new()); 
+        }
+        assert_eq!(q.early_drops, 0); 
+        assert!(q.marks > 10); 
+    }
+
+    #[test]
+    fn codel_enters_and_leaves_the_dropping_state() {
+        let mut rng = StdRng::seed_from_u64(1); 
+        let mut q = Codel::new(QueueLimit::packets(10000), CodelConfig::default()); 
+        let mut now = Duration::ZERO; 
+        let mut dropped = Vec::new(); 
+        // two in and one out each ms, the sojourn time grows past the target.
+        for _ in 0..300 {
+            for _ in 0..2 {
+                q.enqueue(message(100, 0, Ecn::NotCapable, now), now, &mut rng).unwrap(); 
+            }
+            q.dequeue(now, &mut dropped); 
+            now += MS; 
+        }
+        assert!(q.state.dropping); 
+        assert!(!dropped.is_empty()); 
+        assert_eq!(q.state.drops, dropped.len()); 
+        // the sojourn time stays under the target after a drain, no more drops.
+        while q.dequeue(now, &mut dropped).is_some() {}
+        let drops = q.state.drops; 
+        for _ in 0..300 {
+            q.enqueue(message(100, 0, Ecn::NotCapable, now), now, &mut rng).unwrap(); 
+            q.dequeue(now, &mut dropped); 
+            now += MS; 
+        }
+        assert!(!q.state.dropping); 
+        assert_eq!(q.state.drops, drops); 
+    }
+
+    #[test]
+    fn drain_takes_the_packets_without_codel_drops() {
+        let mut rng = StdRng::seed_from_u64(1); 
+        let mut q = Codel::new(QueueLimit::packets(10000), CodelConfig::default()); 
+        for _ in 0..100 {
+            q.enqueue(message(100, 0, Ecn::NotCapable, Duration::ZERO), Duration::ZERO, &mut rng).unwrap(); 
+        }
+        // long past the target, a dequeue would drop.
+        assert_eq!(q.drain().len(), 100); 
+        assert_eq!((q.len(), q.bytes(), q.state.drops), (0, 0, 0)); 
+        let mut q = FqCodel::new(QueueLimit::packets(10000), CodelConfig::default(), 1514); 
+        for _ in 0..10 {
+            q.enqueue(message(100, 0, Ecn::NotCapable, Duration::ZERO), Duration::ZERO, &mut rng).unwrap(); 
+        }
+        assert_eq!(q.drain().len(), 10); 
+        assert_eq!((q.len(), q.bytes()), (0, 0)); 
+        assert_eq!(q.dequeue(Duration::ZERO, &mut Vec::new()).map(|m| m.message_len), None); 
+    }
+
+    #[test]
+    fn drr_shares_by_the_weights() {
+        let mut rng = StdRng::seed_from_u64(1); 
+        let mut q = Drr::new(QueueLimit::packets(1000), vec![3, 1]); 
+        for _ in 0..400 {
+            for class in 0..2 {
+                q.enqueue(message(1000, class, Ecn::NotCapable, Duration::ZERO), Duration::ZERO, &mut rng).unwrap(); 
+            }
+        }
+        let mut sent = [0; 2]; 
+        for _ in 0..400 {
+            sent[q.dequeue(Duration::ZERO, &mut Vec::new()).unwrap().priority as usize] += 1; 
+        }
+        assert!((295..=305).contains(&sent[0]), "{sent:?}"); 
+        assert_eq!(sent[0] + sent[1], 400); 
+    }
+
+    #[test]
+    fn drr_weights_are_capped() {
+        let q = Drr::new(QueueLimit::packets(10), vec![0, usize::MAX]); 
+        assert_eq!(q.weights, vec![1, DRR_MAX_WEIGHT]); 
+    }
+
+    #[test]
+    fn byte_limit_admits_up_to_the_bytes() {
+        let limit = QueueLimit { packets: Some(10), bytes: Some(1000) }; 
+        assert!(limit.admits(0, 900, 100)); 
+        assert!(!limit.admits(0, 901, 100)); 
+        assert!(!limit.admits(10, 0, 1)); 
+    }
+}
@@ -1,32 +1,366 @@
-use std::{sync::{Arc, atomic::{AtomicUsize, Ordering::Relaxed}}, collections::{BTreeMap, LinkedList}, net::{Ipv4Addr, SocketAddrV4}, time::Duration};
+use std::{sync::{Arc, atomic::{AtomicU64, AtomicUsize, Ordering::Relaxed}}, collections::{BTreeMap, LinkedList, hash_map::DefaultHasher}, hash::{Hash, Hasher}, net::{Ipv4Addr, SocketAddrV4}, time::Duration};
 
-use tokio::{sync::{Mutex, mpsc::{self, UnboundedReceiver, UnboundedSender, error::TryRecvError}}, task::yield_now, time::{Instant, sleep}, net::UdpSocket, spawn};
+use tokio::{sync::{Mutex, mpsc::{self, UnboundedReceiver, UnboundedSender}}, time::{Instant, sleep, sleep_until}, net::UdpSocket, spawn, select};
 use lazy_static::lazy_static;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use rand_distr::{Distribution, Normal};
 
-use crate::router::config::drop_packet; 
+use crate::{router::config::drop_packet, shaper::{Conform, TokenBucket}, queue::{DropTail, QueueDiscipline, QueueLimit}, mysocket::{self, CONTROL_LENGTH, CONTROL_PORT, EXTENDED_MARK, SendOptions}, sim::{self, Event}, routing::{self, Mode, DistanceVector, LinkStateDb, Lsa, Paths, Prefix}}; 
 
 #[derive(Debug)]
 pub struct Message {
     pub target: SocketAddrV4,
     pub message: MessageType, 
     pub message_len: usize, 
+    pub kind: MessageKind, 
+    /// routers the packet has been forwarded by. 
+    pub hops: usize, 
+    /// like the TTL of ip, the router that would forward it for the `hop_limit`-th time drops it. 
+    pub hop_limit: usize, 
+    /// when it entered the current output queue, on the clock of `sim::now`. 
+    pub enqueued: Duration, 
+    /// the class in the priority and fair queueing schedulers, the higher the more urgent. 
+    pub priority: u8, 
+    pub ecn: Ecn, 
+    /// set on a piece of a packet split for the mtu of a link, see `Router::reassemble`. 
+    pub fragment: Option<Fragment>, 
+}
+
+/// Where a fragment belongs in the packet it was split from. 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fragment {
+    /// the router that split the packet, and its number for the packet there. 
+    pub router: Ipv4Addr, 
+    pub id: u64, 
+    /// the position of the fragment in the payload of the packet. 
+    pub offset: usize, 
+    /// whether the payload goes on after this fragment. 
+    pub more: bool, 
+}
+
+/// The ECN field of a packet. 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ecn {
+    NotCapable, 
+    /// the sender reacts to the marks, a queue may mark the packet instead of dropping it. 
+    Capable, 
+    /// a queue on the path marked it. 
+    Congested, 
+}
+
+impl Ecn {
+    /// mark the packet if it is ECN-capable, returns whether it is marked. 
+    pub fn mark(&mut self) -> bool {
+        if *self != Ecn::NotCapable {
+            *self = Ecn::Congested; 
+        }
+        *self == Ecn::Congested
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageKind {
+    /// from a client, delivered to `target`. 
+    Data, 
+    /// routing advertisement for the neighbor router `target`, consumed there. 
+    DistanceVector, 
+    /// link-state advertisement for the neighbor router `target`, flooded on from there. 
+    LinkState, 
+    /// an error about a dropped packet, routed back to its source like data. 
+    Error, 
 }
 
 pub const MESSAGE_LENGTH : usize = 2500; 
 pub type MessageType = Box<[u8; MESSAGE_LENGTH]>; 
 
+/// the address header ahead of the payload: ipv4 (4 bytes) and port (2 bytes). 
+pub const HEADER_LENGTH: usize = 6; 
+
+/// take a buffer from `CACHES`, or allocate one if it is empty. 
+pub async fn new_buffer() -> MessageType {
+    CACHES.lock().await.pop_back().unwrap_or_else(|| Box::new([0u8; MESSAGE_LENGTH]))
+}
+
+/// write the control message about a packet to `target` into `buffer`, as from `from`, returns its length. 
+pub fn write_error(buffer: &mut [u8], from: Ipv4Addr, kind: u8, code: u8, target: SocketAddrV4, mtu: u16) -> usize {
+    buffer[..4].copy_from_slice(&from.octets()); 
+    buffer[4..HEADER_LENGTH].copy_from_slice(&CONTROL_PORT.to_le_bytes()); 
+    let c = &mut buffer[HEADER_LENGTH..HEADER_LENGTH + CONTROL_LENGTH]; 
+    c[0] = kind; 
+    c[1] = code; 
+    c[2..6].copy_from_slice(&target.ip().octets()); 
+    c[6..8].copy_from_slice(&target.port().to_le_bytes()); 
+    c[8..].copy_from_slice(&mtu.to_le_bytes()); 
+    HEADER_LENGTH + CONTROL_LENGTH
+}
+
+impl Message {
+    /// a copy of this message in a buffer from `CACHES`. 
+    pub async fn duplicate(&self) -> Message {
+        let mut message = new_buffer().await; 
+        message[..self.message_len].copy_from_slice(&self.message[..self.message_len]); 
+        Message { target: self.target, message, message_len: self.message_len, kind: self.kind, hops: self.hops, hop_limit: self.hop_limit, enqueued: self.enqueued, priority: self.priority, ecn: self.ecn, fragment: self.fragment }
+    }
+
+    /// the sender written in the address header. 
+    pub fn source(&self) -> SocketAddrV4 {
+        let m = &self.message; 
+        SocketAddrV4::new(Ipv4Addr::new(m[0], m[1], m[2], m[3]), m[4] as u16 + ((m[5] as u16) << 8))
+    }
+
+    /// flip one random bit behind the address header, so the packet stays routable. 
+    pub fn corrupt(&mut self, rng: &mut impl Rng) {
+        if self.message_len <= HEADER_LENGTH {
+            return 
+        }
+        let bit = rng.gen_range(HEADER_LENGTH * 8..self.message_len * 8); 
+        self.message[bit / 8] ^= 1 << (bit % 8); 
+    }
+}
+
+/// One directed link out of a router, keyed by the neighbor's ipv4 in `Router::outers`. 
+pub struct Link {
+    /// bits per second. 
+    pub bandwidth: usize, 
+    /// propagation delay, a packet stays in flight for it after the serialization. 
+    pub delay: Duration, 
+    /// probability that a packet is lost on the wire, independently of the others. 
+    pub loss: f64, 
+    /// optional bursty loss, applied after the independent one. 
+    pub gilbert: Option<GilbertElliott>, 
+    /// random variation added to `delay`, packets in flight may overtake each other. 
+    pub jitter: Jitter, 
+    /// probability that a packet skips the flight time and overtakes the packets on the wire. 
+    pub reorder: f64, 
+    /// probability that a packet arrives twice. 
+    pub duplicate: f64, 
+    /// probability that one bit of the payload is flipped. 
+    pub corrupt: f64, 
+    /// the largest packet (header included) the link takes, see `config::FRAGMENT` for the larger ones. 
+    pub mtu: Option<usize>, 
+    pub sender: UnboundedSender<Message>, 
+    /// the packets waiting for the transmitter of this link. 
+    pub queue: Box<dyn QueueDiscipline>, 
+    /// the packet under serialization. 
+    sending: Option<Message>, 
+}
+
+impl Link {
+    pub fn new(bandwidth: usize, limit: QueueLimit, sender: UnboundedSender<Message>) -> Link {
+        Link { bandwidth, delay: Duration::ZERO, loss: 0., gilbert: None, jitter: Jitter::None, reorder: 0., duplicate: 0., corrupt: 0., mtu: None, sender, 
+            queue: Box::new(DropTail::new(limit)), sending: None } 
+    }
+
+    /// take the next packet of the queue (from -> to) into the transmitter, a packet of `len` bytes 
+    /// takes `len * 8 / bandwidth` seconds. 
+    async fn start_next(&mut self, from: Ipv4Addr, to: Ipv4Addr) -> Option<Duration> {
+        let mut dropped = Vec::new(); 
+        let m = self.queue.dequeue(sim::now(), &mut dropped); 
+        for (d, reason) in dropped {
+            let hint = if cfg!(feature = "log-drop") {
+                format!("{reason} on link {from} -> {to}")
+            } else { "".to_string() }; 
+            drop_packet(d.message_len, &hint, d.message).await; 
+        }
+        let m = m?; 
+        let bits = (m.message_len * 8) as f64; 
+        self.sending = Some(m); 
+        Some(Duration::from_secs_f64(bits / self.bandwidth as f64))
+    }
+
+    /// put the serialized packet (from -> to) on the wire, it reaches the next router after `delay`. 
+    pub async fn propagate(&mut self, from: Ipv4Addr, to: Ipv4Addr, message: Message, rng: &mut StdRng) {
+        if self.loss > 0. && rng.gen_bool(self.loss) {
+            let hint = if cfg!(feature = "log-drop") {
+                format!("random loss on link {from} -> {to} (p={})", self.loss)
+            } else { "".to_string() }; 
+            drop_packet(message.message_len, &hint, message.message).await; 
+            return 
+        }
+        if let Some(ref mut ge) = self.gilbert {
+            if ge.step(rng) {
+                let hint = if cfg!(feature = "log-drop") {
+                    format!("bursty loss on link {from} -> {to} in {} state", ge.state())
+                } else { "".to_string() }; 
+                drop_packet(message.message_len, &hint, message.message).await; 
+                return 
+            }
+        }
+        let mut copies = vec![message]; 
+        if self.duplicate > 0. && rng.gen_bool(self.duplicate) {
+            let copy = copies[0].duplicate().await; 
+            copies.push(copy); 
+        }
+        for mut message in copies {
+            if self.corrupt > 0. && rng.gen_bool(self.corrupt) {
+                message.corrupt(rng); 
+            }
+            self.fly(to, message, rng); 
+        }
+    }
+
+    fn fly(&self, to: Ipv4Addr, message: Message, rng: &mut StdRng) {
+        let delay = if self.reorder > 0. && rng.gen_bool(self.reorder) {
+            Duration::ZERO
+        } else {
+            self.jitter.apply(self.delay, rng)
+        }; 
+        if sim::enabled() {
+            sim::schedule(delay, Event::Arrival(to, message)); 
+            return 
+        }
+        if delay.is_zero() {
+            self.sender.send(message).unwrap(); 
+            return 
+        }
+        let sender = self.sender.clone(); 
+        spawn(async move {
+            sleep(delay).await; 
+            sender.send(message).unwrap(); 
+        }); 
+    }
+}
+
+/// Distribution of the extra flight time around the link delay. 
+#[derive(Debug, Clone, Copy)]
+pub enum Jitter {
+    None, 
+    /// uniform in [-j, +j]. 
+    Uniform(Duration), 
+    /// normal with the given standard deviation. 
+    Normal(Duration), 
+}
+
+impl Jitter {
+    /// sample the flight time of one packet, never below zero. 
+    pub fn apply(&self, delay: Duration, rng: &mut impl Rng) -> Duration {
+        let offset = match *self {
+            Jitter::None => return delay, 
+            Jitter::Uniform(j) if j.is_zero() => return delay, 
+            Jitter::Uniform(j) => rng.gen_range(-j.as_secs_f64()..=j.as_secs_f64()), 
+            Jitter::Normal(sd) => Normal::new(0., sd.as_secs_f64()).map_or(0., |n| n.sample(rng)), 
+        }; 
+        Duration::from_secs_f64((delay.as_secs_f64() + offset).max(0.))
+    }
+}
+
+/// Two-state (good/bad) Markov loss model of Gilbert-Elliott. 
+#[derive(Debug, Clone, Copy)]
+pub struct GilbertElliott {
+    pub good_to_bad: f64, 
+    pub bad_to_good: f64, 
+    pub loss_good: f64, 
+    pub loss_bad: f64, 
+    bad: bool, 
+}
+
+impl GilbertElliott {
+    pub fn new(good_to_bad: f64, bad_to_good: f64, loss_good: f64, loss_bad: f64) -> GilbertElliott {
+        GilbertElliott { good_to_bad, bad_to_good, loss_good, loss_bad, bad: false }
+    }
+
+    /// move the chain one packet ahead, returns whether this packet is lost. 
+    pub fn step(&mut self, rng: &mut impl Rng) -> bool {
+        let flip = if self.bad { self.bad_to_good } else { self.good_to_bad }; 
+        if rng.gen_bool(flip) {
+            self.bad = !self.bad; 
+        }
+        rng.gen_bool(if self.bad { self.loss_bad } else { self.loss_good })
+    }
+
+    pub const fn state(&self) -> &'static str {
+        if self.bad { "bad" } else { "good" }
+    }
+}
+
 pub struct Router {
     ipv4addr: Ipv4Addr, 
-    outers: Mutex<BTreeMap<Ipv4Addr, (usize, UnboundedSender<Message>)>>, 
+    outers: Mutex<BTreeMap<Ipv4Addr, Link>>, 
     receiver: Mutex<UnboundedReceiver<Message>>, 
     sender: UnboundedSender<Message>, 
-    pub queue_size: AtomicUsize, 
-    routers: Mutex<BTreeMap<Ipv4Addr, (f64, Ipv4Addr)>>, 
+    /// the queue limit of the links made from now on. 
+    pub queue_limit: std::sync::Mutex<QueueLimit>, 
+    /// the most bytes queued on all the links of the router at once. 
+    pub peak_bytes: AtomicUsize, 
+    routers: Mutex<BTreeMap<Prefix, Route>>, 
+    pub ecmp: std::sync::Mutex<Ecmp>, 
+    /// the round robin position of `Ecmp::Packet`. 
+    spray: AtomicUsize, 
+    /// host addresses behind this router besides its own `ipv4addr`, see `Router::owns`. 
+    subnets: Mutex<Vec<Prefix>>, 
+    /// the random stream of this router, derived from `config::SEED` and `ipv4addr`. 
+    rng: Mutex<StdRng>, 
+    /// the rate plans of the source hosts attached to this router, see `Router::police`. 
+    pub buckets: Mutex<BTreeMap<Ipv4Addr, TokenBucket>>, 
+    /// the number of the next packet this router splits. 
+    fragment_ids: AtomicU64, 
+    /// the fragments to this router, see `Router::reassemble`. 
+    reassembly: Mutex<Reassembly>, 
+    dv: Mutex<DistanceVector>, 
+    lsdb: Mutex<LinkStateDb>, 
+}
+
+/// One entry of the routing table. 
+#[derive(Debug, Clone)]
+pub struct Route {
+    pub metric: f64, 
+    /// the equal-cost next hops, see `Ecmp` for the choice among them. 
+    pub next_hops: Vec<Ipv4Addr>, 
+    /// set by the ROUTE command, the dynamic routing never replaces it. 
+    pub is_static: bool, 
 }
 
-const DEFAULT_QUEUE_SIZE: AtomicUsize = AtomicUsize::new(5);
+impl Route {
+    pub fn dynamic(metric: f64, next_hops: Vec<Ipv4Addr>) -> Route {
+        Route { metric, next_hops, is_static: false }
+    }
+
+    /// the periodic update prefers the larger metric, and keeps every next hop of an equal one. 
+    fn offer(&mut self, metric: f64, next_hop: Ipv4Addr) {
+        if self.is_static {
+            return 
+        }
+        if routing::same_cost(self.metric, metric) {
+            if !self.next_hops.contains(&next_hop) {
+                self.next_hops.push(next_hop); 
+            }
+        } else if self.metric < metric {
+            *self = Route::dynamic(metric, vec![next_hop]); 
+        }
+    }
+}
+
+/// How a router spreads the packets over equal-cost next hops. 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ecmp {
+    /// hash the (source, target) pair, the packets of one flow keep their order. 
+    Flow, 
+    /// round robin over the next hops, packet by packet. 
+    Packet, 
+}
+
+pub const DEFAULT_QUEUE_SIZE: usize = 5;
+
+pub const PERIOD_UPDATE: Duration = Duration::from_secs(20); 
+
+/// the time the fragments of a packet wait for the others at the target router. 
+pub const REASSEMBLY_TIMEOUT: Duration = Duration::from_secs(5); 
 
-const PERIOD_UPDATE: Duration = Duration::from_secs(20); 
+/// The fragments of one packet arrived so far. 
+struct Pending {
+    /// when the first of them arrived. 
+    since: Duration, 
+    pieces: Vec<Message>, 
+}
+
+/// The fragments to a router, by the router and number of their packet. 
+#[derive(Default)]
+struct Reassembly {
+    pending: BTreeMap<(Ipv4Addr, u64), Pending>, 
+    /// the packets put together in the last `REASSEMBLY_TIMEOUT`, and when, the late duplicates of 
+    /// their fragments are dropped. 
+    done: BTreeMap<(Ipv4Addr, u64), Duration>, 
+}
 
 lazy_static! {
     pub static ref GLOBAL_ROUTERS: Mutex<BTreeMap<Ipv4Addr, Arc<Router>>> = Mutex::const_new(BTreeMap::new()); 
@@ -36,9 +370,27 @@ lazy_static! {
 
 pub static SERVER_SOCKET: Option<UdpSocket> = None; 
 
+/// the router a client at `ip` is attached to: the router with that address, or else the one owning 
+/// the longest subnet containing it. 
+pub async fn ingress_router(ip: Ipv4Addr) -> Option<Arc<Router>> {
+    let globals = GLOBAL_ROUTERS.lock().await; 
+    if let Some(r) = globals.get(&ip) {
+        return Some(r.clone())
+    }
+    let mut best: Option<(u8, &Arc<Router>)> = None; 
+    for r in globals.values() {
+        for p in r.subnets.lock().await.iter() {
+            if p.contains(ip) && best.is_none_or(|(len, _)| p.prefix_len() > len) {
+                best = Some((p.prefix_len(), r)); 
+            }
+        }
+    }
+    best.map(|(_, r)| r.clone())
+}
+
 impl Router {
 
-    pub const fn outers(&self) -> &Mutex<BTreeMap<Ipv4Addr, (usize, UnboundedSender<Message>)>> {
+    pub const fn outers(&self) -> &Mutex<BTreeMap<Ipv4Addr, Link>> {
         &self.outers
     }
 
@@ -50,6 +402,32 @@ impl Router {
         self.ipv4addr
     }
 
+    pub const fn subnets(&self) -> &Mutex<Vec<Prefix>> {
+        &self.subnets
+    }
+
+    /// the own address as a /32 and the owned subnets. 
+    pub async fn prefixes(&self) -> Vec<Prefix> {
+        let mut prefixes = vec![Prefix::host(self.ipv4addr)]; 
+        prefixes.extend(self.subnets.lock().await.iter().copied()); 
+        prefixes
+    }
+
+    /// whether a packet to `ip` is delivered by this router. 
+    pub async fn owns(&self, ip: Ipv4Addr) -> bool {
+        ip == self.ipv4addr || self.subnets.lock().await.iter().any(|p| p.contains(ip))
+    }
+
+    fn seeded_rng(ipv4: Ipv4Addr) -> StdRng {
+        let ip = u32::from(ipv4) as u64; 
+        StdRng::seed_from_u64(config::SEED.load(Relaxed) ^ ip.wrapping_mul(0x9e37_79b9_7f4a_7c15))
+    }
+
+    /// restart the random stream from the current `config::SEED`. 
+    pub async fn reseed(&self) {
+        *self.rng.lock().await = Router::seeded_rng(self.ipv4addr); 
+    }
+
     pub async fn from_ipv4addr(ipv4: Ipv4Addr, udp: Arc<UdpSocket>) -> Arc<Router> {
         let mut guard = GLOBAL_ROUTERS.lock().await; 
         let entry = guard.entry(ipv4);
@@ -62,8 +440,18 @@ impl Router {
                 outers: Mutex::new(BTreeMap::new()), 
                 receiver: Mutex::new(r), 
                 sender: s, 
-                queue_size: DEFAULT_QUEUE_SIZE, 
+                queue_limit: std::sync::Mutex::new(QueueLimit::packets(DEFAULT_QUEUE_SIZE)), 
+                peak_bytes: AtomicUsize::new(0), 
                 routers: Mutex::new(BTreeMap::new()), 
+                ecmp: std::sync::Mutex::new(Ecmp::Flow), 
+                spray: AtomicUsize::new(0), 
+                subnets: Mutex::new(Vec::new()), 
+                rng: Mutex::new(Router::seeded_rng(ipv4)), 
+                buckets: Mutex::new(BTreeMap::new()), 
+                fragment_ids: AtomicU64::new(0), 
+                reassembly: Mutex::new(Reassembly::default()), 
+                dv: Mutex::new(DistanceVector::default()), 
+                lsdb: Mutex::new(LinkStateDb::default()), 
             }) 
         }); 
         if created {
@@ -71,160 +459,546 @@ impl Router {
             if cfg!(feature = "log-deal") {
                 eprintln!("\x1b[32;1m[{:21}] ip: {}\x1b[0m", "Router Create", value.ipv4addr); 
             }
-            spawn(async move {
-                let t = udp; 
-                value.work(&t).await; 
-            }); 
+            if sim::enabled() {
+                sim::schedule(routing::period(), Event::RouteUpdate(value.ipv4addr)); 
+            } else {
+                spawn(async move {
+                    let t = udp; 
+                    value.work(&t).await; 
+                }); 
+            }
         }
         value.clone()
     }
 
+    /// wait for an arriving packet, the end of a serialization on any link, the next routing update or 
+    /// the reassembly timeout of a packet, whichever comes first. 
     pub async fn work(&self, sender: &UdpSocket) {
-        let mut queue = LinkedList::new();
-        let mut to_send: Option<(Message, f64)> = None; 
-        let mut last_instant = Instant::now(); 
+        let mut receiver = self.receiver.lock().await; 
+        // the links whose transmitter is busy, and when it is done. 
+        let mut done_at: BTreeMap<Ipv4Addr, Instant> = BTreeMap::new(); 
+        let mut next_update = Instant::now() + routing::period(); 
         loop {
-            let mut receiver = self.receiver.lock().await; 
-            'recv: loop {
-                match receiver.try_recv() { 
-                    Ok(r) => {
-                        if queue.len() < self.queue_size.load(Relaxed) {
-                            queue.push_back(r); 
-                        } else {
-                            let p = if cfg!(feature = "log-drop") {
-                                format!("queue buffer overflow; router: {}", self.ipv4addr)
-                            } else { 
-                                "".to_string() 
-                            }; 
-                            drop_packet(r.message_len, &p, r.message).await; 
-                        }
-                    },
-                    Err(TryRecvError::Empty) => {
-                        break 'recv; 
-                    },
-                    _ => unreachable!(), 
-                }
-            };
-            drop(receiver); 
-            if let None = to_send {
-                // move a new packet from queue to it. 
-                if let Some(m) = queue.pop_front() {
-                    let ml = m.message_len; 
-                    to_send = Some((m, ((ml + 2) * 8) as f64)); 
+            let next_done = done_at.iter().min_by_key(|(_, t)| **t).map(|(p, t)| (*p, *t)); 
+            let next_expiry = self.next_expiry().await.map(|at| Instant::now() + at.saturating_sub(sim::now())); 
+            select! {
+                m = receiver.recv() => {
+                    // the router keeps a sender itself, the channel is never closed. 
+                    if let Some((p, t)) = self.arrive(m.unwrap(), sender).await {
+                        done_at.insert(p, Instant::now() + t); 
+                    }
+                }, 
+                _ = sleep_until(next_done.map_or(next_update, |(_, t)| t)), if next_done.is_some() => {
+                    let (p, last) = next_done.unwrap(); 
+                    match self.transmit_done(p).await {
+                        Some(t) => done_at.insert(p, last + t), 
+                        None => done_at.remove(&p), 
+                    }; 
+                }, 
+                _ = sleep_until(next_expiry.unwrap_or(next_update)), if next_expiry.is_some() => {
+                    self.expire_fragments().await; 
+                }, 
+                _ = sleep_until(next_update) => {
+                    self.update_routes().await; 
+                    next_update += routing::period(); 
+                }, 
+            }
+        }
+    }
+
+    /// put a packet into the queue of the link towards `p`, or drop it as the queue discipline decides. 
+    async fn admit(&self, p: Ipv4Addr, link: &mut Link, mut r: Message) {
+        r.enqueued = sim::now(); 
+        let admitted = link.queue.enqueue(r, sim::now(), &mut *self.rng.lock().await); 
+        if let Err((r, reason)) = admitted {
+            let p = if cfg!(feature = "log-drop") {
+                format!("{reason} on link {} -> {p}", self.ipv4addr)
+            } else { 
+                "".to_string() 
+            }; 
+            drop_packet(r.message_len, &p, r.message).await; 
+        }
+    }
+
+    /// replace the queue discipline of the link towards `p`, the queued packets move to the new one. 
+    pub async fn set_discipline(&self, p: Ipv4Addr, mut queue: Box<dyn QueueDiscipline>) {
+        let mut outers = self.outers.lock().await; 
+        let Some(link) = outers.get_mut(&p) else {
+            return 
+        }; 
+        queue.set_limit(link.queue.limit()); 
+        let mut old = std::mem::replace(&mut link.queue, queue); 
+        for m in old.drain() {
+            self.admit(p, link, m).await; 
+        }
+    }
+
+    /// hold a packet of `len` bytes from the client `source` to its rate plan, returns the wait before 
+    /// it enters the network, or the drop hint. 
+    pub async fn police(&self, source: Ipv4Addr, len: usize) -> Result<Duration, String> {
+        let mut buckets = self.buckets.lock().await; 
+        let Some(bucket) = buckets.get_mut(&source) else {
+            return Ok(Duration::ZERO)
+        }; 
+        bucket.take(len, sim::ingress_now()).ok_or_else(|| match bucket.conform {
+            _ if !cfg!(feature = "log-drop") => "".to_string(), 
+            Conform::Police => format!("policed, source {source} over {} bit/s; router: {}", bucket.rate, self.ipv4addr), 
+            Conform::Shape(bound) => format!("shaper backlog over {bound:?}, source {source} at {} bit/s; router: {}", bucket.rate, self.ipv4addr), 
+        })
+    }
+
+    /// send the packet to the actual position, and recycle its buffer. 
+    async fn deliver(&self, sender: &UdpSocket, m: Message) {
+        config::RECEIVE_PACKETS.fetch_add(1, Relaxed); 
+        config::RECEIVE_BYTES.fetch_add(m.message_len - HEADER_LENGTH, Relaxed); 
+        if m.ecn == Ecn::Congested {
+            // the mark goes in an extended header, see `mysocket::SendOptions`. 
+            let options = SendOptions { ecn_capable: true, congestion_experienced: true, ..SendOptions::default() }.encode(); 
+            let mut marked = Vec::with_capacity(m.message_len + 6 + options.len()); 
+            marked.extend_from_slice(&EXTENDED_MARK); 
+            marked.extend_from_slice(&(options.len() as u16).to_le_bytes()); 
+            marked.extend_from_slice(&m.message[..HEADER_LENGTH]); 
+            marked.extend_from_slice(&options); 
+            marked.extend_from_slice(&m.message[HEADER_LENGTH..m.message_len]); 
+            sender.send_to(&marked, m.target).await.unwrap(); 
+        } else {
+            sender.send_to(&m.message[..m.message_len], m.target).await.unwrap(); 
+        }
+        CACHES.lock().await.push_back(m.message); 
+    }
+
+    /// the neighbor to forward the packet to, or the drop hint. 
+    async fn next_hop(&self, i: &Message) -> Result<Ipv4Addr, String> {
+        let target = if matches!(i.kind, MessageKind::Data | MessageKind::Error) {
+            let routers = self.routers.lock().await; 
+            routing::longest_match(&routers, *i.target.ip()).map(|v| self.choose(&v.next_hops, i))
+        } else {
+            // routing messages only go to a neighbor. 
+            Some(*i.target.ip())
+        }; 
+        match target {
+            Some(p) => Ok(p), 
+            None => Err(if cfg!(feature = "log-drop") {
+                format!("packet (target {}:{:5}) fails with the missing routing item; router: {}", i.target.ip(), i.target.port(), self.ipv4addr)
+            } else {
+                "".into()
+            }), 
+        }
+    }
+
+    /// pick one of the equal-cost next hops for the packet. 
+    fn choose(&self, hops: &[Ipv4Addr], i: &Message) -> Ipv4Addr {
+        if hops.len() == 1 {
+            return hops[0]
+        }
+        let index = match *self.ecmp.lock().unwrap() {
+            Ecmp::Flow => {
+                let mut hasher = DefaultHasher::new(); 
+                (i.source(), i.target).hash(&mut hasher); 
+                hasher.finish() as usize
+            }, 
+            Ecmp::Packet => self.spray.fetch_add(1, Relaxed), 
+        }; 
+        hops[index % hops.len()]
+    }
+
+    /// a packet reaches this router, it is delivered at once or queued on the link to its next hop. 
+    /// returns that link and the serialization time if the packet occupies its transmitter. 
+    pub async fn arrive(&self, m: Message, sender: &UdpSocket) -> Option<(Ipv4Addr, Duration)> {
+        let mut m = self.reassemble(m).await?; 
+        if *m.target.ip() == self.ipv4addr {
+            match m.kind {
+                MessageKind::DistanceVector => {
+                    self.receive_vector(m).await; 
+                    return None
+                }, 
+                MessageKind::LinkState => {
+                    self.receive_lsa(m).await; 
+                    return None
+                }, 
+                MessageKind::Data | MessageKind::Error => {}, 
+            }
+        }
+        if self.owns(*m.target.ip()).await {
+            self.deliver(sender, m).await; 
+            return None
+        }
+        // the routing messages only cross one link, the hop limit is about the forwarded ones. 
+        if matches!(m.kind, MessageKind::Data | MessageKind::Error) && m.hops + 1 >= m.hop_limit {
+            let loops = config::LOOP_PACKETS.fetch_add(1, Relaxed) + 1; 
+            let hint = if cfg!(feature = "log-drop") {
+                format!("TTL exceeded (target {}, hops {}, loops so far {loops}); router: {}", m.target, m.hops, self.ipv4addr)
+            } else { "".to_string() }; 
+            self.reject(m, &hint, mysocket::TIME_EXCEEDED, mysocket::HOP_LIMIT, 0).await; 
+            return None
+        }
+        let p = match self.next_hop(&m).await {
+            Ok(p) => p, 
+            Err(hint) => {
+                self.reject(m, &hint, mysocket::UNREACHABLE, mysocket::NO_ROUTE, 0).await; 
+                return None
+            },
+        }; 
+        m.hops += 1; 
+        let mut outers = self.outers.lock().await; 
+        let Some(link) = outers.get_mut(&p) else {
+            let hint = if cfg!(feature = "log-drop") {
+                format!("impossible miss router op; locate router: {}", self.ipv4addr) 
+            } else { "".to_string() }; 
+            drop_packet(m.message_len, &hint, m.message).await; 
+            return None
+        }; 
+        match link.mtu {
+            Some(mtu) if m.message_len > mtu && !config::FRAGMENT.load(Relaxed) => {
+                let hint = if cfg!(feature = "log-drop") {
+                    format!("{} bytes over the mtu {mtu} of link {} -> {p}", m.message_len, self.ipv4addr)
+                } else { "".to_string() }; 
+                self.reject(m, &hint, mysocket::UNREACHABLE, mysocket::FRAG_NEEDED, mtu as u16).await; 
+                return None
+            }, 
+            Some(mtu) if m.message_len > mtu => {
+                for piece in self.fragment(m, mtu).await {
+                    self.admit(p, link, piece).await; 
                 }
+            }, 
+            _ => self.admit(p, link, m).await, 
+        }
+        let started = if link.sending.is_none() {
+            link.start_next(self.ipv4addr, p).await.map(|t| (p, t))
+        } else {
+            None
+        }; 
+        self.peak_bytes.fetch_max(outers.values().map(|l| l.queue.bytes()).sum(), Relaxed); 
+        started
+    }
+
+    /// the bytes queued on all the links of the router. 
+    pub async fn queued_bytes(&self) -> usize {
+        self.outers.lock().await.values().map(|l| l.queue.bytes()).sum()
+    }
+
+    /// the serialization on the link towards `p` is done, the packet goes on the wire. returns the 
+    /// serialization time of the next packet of the link if any. 
+    pub async fn transmit_done(&self, p: Ipv4Addr) -> Option<Duration> {
+        let mut outers = self.outers.lock().await; 
+        let link = outers.get_mut(&p)?; 
+        if let Some(m) = link.sending.take() {
+            link.propagate(self.ipv4addr, p, m, &mut *self.rng.lock().await).await; 
+        }
+        link.start_next(self.ipv4addr, p).await
+    }
+
+    /// split the packet into fragments of at most `mtu` bytes, each with the address header. a fragment 
+    /// is split again into fragments of the same packet. 
+    async fn fragment(&self, m: Message, mtu: usize) -> Vec<Message> {
+        let whole = m.fragment.unwrap_or_else(|| Fragment { router: self.ipv4addr, id: self.fragment_ids.fetch_add(1, Relaxed), offset: 0, more: false }); 
+        let payload = &m.message[HEADER_LENGTH..m.message_len]; 
+        let size = mtu - HEADER_LENGTH; 
+        let mut pieces = Vec::new(); 
+        for (i, part) in payload.chunks(size).enumerate() {
+            let mut message = new_buffer().await; 
+            message[..HEADER_LENGTH].copy_from_slice(&m.message[..HEADER_LENGTH]); 
+            message[HEADER_LENGTH..HEADER_LENGTH + part.len()].copy_from_slice(part); 
+            let fragment = Fragment { offset: whole.offset + i * size, more: whole.more || (i + 1) * size < payload.len(), ..whole }; 
+            pieces.push(Message { message, message_len: HEADER_LENGTH + part.len(), fragment: Some(fragment), ..m }); 
+        }
+        config::FRAGMENTED_PACKETS.fetch_add(1, Relaxed); 
+        CACHES.lock().await.push_back(m.message); 
+        pieces
+    }
+
+    /// keep a fragment to this router until all of its packet is here, then the packet goes on in one 
+    /// piece. the other messages pass through. 
+    async fn reassemble(&self, m: Message) -> Option<Message> {
+        let Some(f) = m.fragment else {
+            return Some(m)
+        }; 
+        if !self.owns(*m.target.ip()).await {
+            return Some(m)
+        }
+        let now = sim::now(); 
+        let key = (f.router, f.id); 
+        let mut reassembly = self.reassembly.lock().await; 
+        reassembly.done.retain(|_, at| now.saturating_sub(*at) < REASSEMBLY_TIMEOUT); 
+        if reassembly.done.contains_key(&key) {
+            let hint = if cfg!(feature = "log-drop") {
+                format!("late fragment (from {}, packet {} of {}) already put together; router: {}", m.source(), key.1, key.0, self.ipv4addr)
+            } else { "".to_string() }; 
+            drop_packet(m.message_len, &hint, m.message).await; 
+            return None
+        }
+        let pending = reassembly.pending.entry(key).or_insert_with(|| {
+            if sim::enabled() {
+                sim::schedule(REASSEMBLY_TIMEOUT, Event::ReassemblyTimeout(self.ipv4addr)); 
+            }
+            Pending { since: now, pieces: Vec::new() }
+        }); 
+        pending.pieces.push(m); 
+        pending.pieces.sort_by_key(|p| p.fragment.unwrap().offset); 
+        // whole when the pieces cover the payload up to the end of the last fragment, without a gap. 
+        let mut covered = 0; 
+        let mut total = None; 
+        for p in &pending.pieces {
+            let f = p.fragment.unwrap(); 
+            if f.offset > covered {
+                return None
+            }
+            covered = covered.max(f.offset + p.message_len - HEADER_LENGTH); 
+            if !f.more {
+                total = Some(f.offset + p.message_len - HEADER_LENGTH); 
+            }
+        }
+        if total.is_none_or(|total| covered < total) {
+            return None
+        }
+        let mut pieces = reassembly.pending.remove(&key).unwrap().pieces.into_iter(); 
+        reassembly.done.insert(key, now); 
+        let mut whole = pieces.next().unwrap(); 
+        for p in pieces {
+            let offset = HEADER_LENGTH + p.fragment.unwrap().offset; 
+            let len = p.message_len - HEADER_LENGTH; 
+            whole.message[offset..offset + len].copy_from_slice(&p.message[HEADER_LENGTH..p.message_len]); 
+            whole.message_len = whole.message_len.max(offset + len); 
+            if p.ecn == Ecn::Congested {
+                whole.ecn = Ecn::Congested; 
             }
-            if let Some((ref i, ref mut val)) = to_send {
-                if *i.target.ip() == self.ipv4addr {
-                    // send the packet to the actual position! 
-                    sender.send_to(&i.message[..i.message_len], i.target).await.unwrap(); 
-                    CACHES.lock().await.push_back(to_send.unwrap().0.message); 
-                    to_send = None; 
+            CACHES.lock().await.push_back(p.message); 
+        }
+        whole.fragment = None; 
+        config::REASSEMBLED_PACKETS.fetch_add(1, Relaxed); 
+        Some(whole)
+    }
+
+    /// drop the packets whose fragments did not all arrive in `REASSEMBLY_TIMEOUT`, the source learns 
+    /// it if the first fragment is here. 
+    pub async fn expire_fragments(&self) {
+        let now = sim::now(); 
+        let mut reassembly = self.reassembly.lock().await; 
+        let expired: Vec<_> = reassembly.pending.iter()
+            .filter(|(_, r)| now.saturating_sub(r.since) >= REASSEMBLY_TIMEOUT)
+            .map(|(k, _)| *k)
+            .collect(); 
+        for key in expired {
+            for p in reassembly.pending.remove(&key).unwrap().pieces {
+                let hint = if cfg!(feature = "log-drop") {
+                    format!("fragment reassembly timeout (from {}, packet {} of {}); router: {}", p.source(), key.1, key.0, self.ipv4addr)
+                } else { "".to_string() }; 
+                if p.fragment.unwrap().offset == 0 {
+                    self.reject(p, &hint, mysocket::TIME_EXCEEDED, mysocket::REASSEMBLY, 0).await; 
                 } else {
-                    sleep(Duration::from_millis(100)).await; 
-                    let router = self.routers.lock().await; 
-                    let target = router.get(i.target.ip()).map(|v| v.1);
-                    drop(router); 
-                    match target {
-                        Some(p) => {
-                            let sender = self.outers.lock().await.get(&p).map(|a| (a.0, a.1.clone())); 
-                            match sender {
-                                Some((bw, send)) => {
-                                    let bw = bw as f64 / 10.; 
-                                    *val -= bw; 
-                                    if *val <= 0. {
-                                        send.send(to_send.unwrap().0).unwrap(); 
-                                        to_send = None; 
-                                    } 
-                                },
-                                None => {
-                                    let hint = if cfg!(feature = "log-drop") {
-                                        format!("impossible miss router op; locate router: {}", self.ipv4addr) 
-                                    } else { "".to_string() }; 
-                                    drop_packet(i.message_len, &hint, to_send.unwrap().0.message).await; 
-                                    to_send = None; 
-                                },
-                            }
-                        },
-                        None => {
-                            let hint = 
-                                if cfg!(feature = "log-drop") {
-                                    format!("packet (target {}:{:5}) fails with the missing routing item; router: {}", i.target.ip(), i.target.port(), self.ipv4addr)
-                                } else {
-                                    "".into()
-                                }; 
-                            drop_packet(i.message_len, &hint, to_send.unwrap().0.message).await; 
-                            to_send = None; 
-                        },
-                    }
+                    drop_packet(p.message_len, &hint, p.message).await; 
                 }
             }
-            let now = Instant::now(); 
-            if now - last_instant > PERIOD_UPDATE {
-                let origin_items; 
-                let globals = GLOBAL_ROUTERS.lock().await; 
-                let mut routers = self.routers.lock().await; 
-                origin_items = routers.len(); 
-                routers.clear(); 
-                {
-                    let outer = self.outers.lock().await; 
-                    for (t, (bw, _)) in outer.iter() {
-                        let entry = routers.entry(*t);
-                        if *bw == 0 {
-                            continue 
-                        }
-                        let speed = 1. / *bw as f64; 
-                        entry.and_modify(|v| {
-                            if v.0 < speed {
-                                *v = (speed, *t); 
-                            }
-                        }).or_insert((speed, *t)); 
-                    }
+        }
+    }
+
+    /// when the oldest incomplete packet times out, on the clock of `sim::now`. 
+    async fn next_expiry(&self) -> Option<Duration> {
+        self.reassembly.lock().await.pending.values().map(|p| p.since + REASSEMBLY_TIMEOUT).min()
+    }
+
+    /// drop the packet, and report it to its source when the errors are on. 
+    async fn reject(&self, m: Message, hint: &str, kind: u8, code: u8, mtu: u16) {
+        // never report a lost error, or two routers could keep reporting to each other. 
+        if m.kind == MessageKind::Data && config::ERRORS.load(Relaxed) {
+            let mut message = new_buffer().await; 
+            let len = write_error(message.as_mut_slice(), self.ipv4addr, kind, code, m.target, mtu); 
+            self.originate(Message { target: m.source(), message, message_len: len, kind: MessageKind::Error, hops: 0, hop_limit: config::MAX_HOPS.load(Relaxed), enqueued: Duration::ZERO, priority: u8::MAX, ecn: Ecn::NotCapable, fragment: None }); 
+        }
+        drop_packet(m.message_len, hint, m.message).await; 
+    }
+
+    /// a packet made by the router itself, it goes through the queue like the others. 
+    fn originate(&self, m: Message) {
+        if sim::enabled() {
+            sim::schedule(Duration::ZERO, Event::Arrival(self.ipv4addr, m)); 
+        } else {
+            self.sender.send(m).unwrap(); 
+        }
+    }
+
+    async fn receive_vector(&self, m: Message) {
+        if routing::mode() == Mode::DistanceVector {
+            let config = routing::dv_config(); 
+            let vector = routing::decode_vector(&m.message[HEADER_LENGTH..m.message_len]); 
+            let own = self.prefixes().await; 
+            let mut dv = self.dv.lock().await; 
+            if dv.receive(&own, *m.source().ip(), &vector, sim::now(), &config) {
+                self.install_routes(dv.table(&config)).await; 
+            }
+        }
+        CACHES.lock().await.push_back(m.message); 
+    }
+
+    /// time out the learnt routes and send a vector to every neighbor. 
+    async fn advertise_vectors(&self) {
+        let config = routing::dv_config(); 
+        let own = self.prefixes().await; 
+        let mut dv = self.dv.lock().await; 
+        if dv.expire(sim::now(), &config) {
+            self.install_routes(dv.table(&config)).await; 
+        }
+        let neighbors: Vec<Ipv4Addr> = self.outers.lock().await.keys().copied().collect(); 
+        for n in neighbors {
+            let vector = dv.advertisement(&own, n, &config); 
+            let mut message = new_buffer().await; 
+            message[..4].copy_from_slice(&self.ipv4addr.octets()); 
+            message[4..HEADER_LENGTH].fill(0); 
+            let len = HEADER_LENGTH + routing::encode_vector(&vector, &mut message[HEADER_LENGTH..]); 
+            self.originate(Message { target: SocketAddrV4::new(n, 0), message, message_len: len, kind: MessageKind::DistanceVector, hops: 0, hop_limit: config::MAX_HOPS.load(Relaxed), enqueued: Duration::ZERO, priority: u8::MAX, ecn: Ecn::NotCapable, fragment: None }); 
+        }
+    }
+
+    async fn receive_lsa(&self, m: Message) {
+        if let (Mode::LinkState(_), Some(lsa)) = (routing::mode(), Lsa::decode(&m.message[HEADER_LENGTH..m.message_len])) {
+            let mut db = self.lsdb.lock().await; 
+            if db.receive(lsa.clone(), sim::now()) {
+                if let Some(aged) = db.get(lsa.origin, sim::now()) {
+                    self.flood(&Lsa { age: aged.age + routing::LS_TRANSIT_AGE, ..aged }, Some(*m.source().ip())).await; 
                 }
-                let p: Vec<_> = self.outers.lock().await.iter().map(|(ipv4, (bw, _))| (*ipv4, *bw)).collect(); 
-                for (ip, bw) in p {
-                    let speed = 1. / bw as f64; 
-                    if bw == 0 { continue }
-                    let g3 = globals.get(&ip); 
-                    match g3 {
-                        Some(g3) => {
-                            let r2 = g3.routers.lock().await;
-                            for (target, (sp2, _)) in r2.iter() {
-                                if *target == self.ipv4addr { continue }
-                                let entry = routers.entry(*target); 
-                                let speed = speed + sp2; 
-                                entry.and_modify(|v| {
-                                    if v.0 < speed {
-                                        *v = (speed, g3.ipv4addr); 
-                                    }
-                                }).or_insert((speed, g3.ipv4addr)); 
-                            }
-                        },
-                        None => {},
-                    }
+                self.install_routes(db.table(self.ipv4addr)).await; 
+            }
+        }
+        CACHES.lock().await.push_back(m.message); 
+    }
+
+    /// send the advertisement to every neighbor but the one it comes from. 
+    async fn flood(&self, lsa: &Lsa, from: Option<Ipv4Addr>) {
+        let neighbors: Vec<Ipv4Addr> = self.outers.lock().await.keys().copied().filter(|n| Some(*n) != from).collect(); 
+        for n in neighbors {
+            let mut message = new_buffer().await; 
+            message[..4].copy_from_slice(&self.ipv4addr.octets()); 
+            message[4..HEADER_LENGTH].fill(0); 
+            let len = HEADER_LENGTH + lsa.encode(&mut message[HEADER_LENGTH..]); 
+            self.originate(Message { target: SocketAddrV4::new(n, 0), message, message_len: len, kind: MessageKind::LinkState, hops: 0, hop_limit: config::MAX_HOPS.load(Relaxed), enqueued: Duration::ZERO, priority: u8::MAX, ecn: Ecn::NotCapable, fragment: None }); 
+        }
+    }
+
+    /// age out the database, then flood a new advertisement of the own links and run SPF. 
+    pub async fn originate_lsa(&self) {
+        let Mode::LinkState(metric) = routing::mode() else { return }; 
+        let links = self.outers.lock().await.iter().map(|(t, link)| (*t, metric.cost(link))).collect(); 
+        let subnets = self.subnets.lock().await.clone(); 
+        let mut db = self.lsdb.lock().await; 
+        db.expire(sim::now()); 
+        let lsa = db.originate(self.ipv4addr, links, subnets, sim::now()); 
+        self.flood(&lsa, None).await; 
+        self.install_routes(db.table(self.ipv4addr)).await; 
+    }
+
+    /// add (or replace) a static route towards `target`. 
+    pub async fn add_static_route(&self, target: Prefix, next_hops: Vec<Ipv4Addr>) {
+        self.routers.lock().await.insert(target, Route { metric: 0., next_hops, is_static: true }); 
+    }
+
+    /// remove the static route towards `target`, returns whether there is one. the computed route 
+    /// comes back at once, see `Router::computed_routes`. 
+    pub async fn remove_static_route(&self, target: Prefix) -> bool {
+        let mut routers = self.routers.lock().await; 
+        let removed = match routers.get(&target) {
+            Some(r) if r.is_static => routers.remove(&target).is_some(), 
+            _ => false, 
+        }; 
+        drop(routers); 
+        if removed {
+            if let Some(table) = self.computed_routes().await {
+                self.install_routes(table).await; 
+            }
+        }
+        removed
+    }
+
+    /// the table the current routing mode computes for this router; `None` in the periodic mode, whose 
+    /// next update rebuilds the table anyway. 
+    async fn computed_routes(&self) -> Option<Paths<Prefix>> {
+        match routing::mode() {
+            Mode::Periodic => None, 
+            Mode::Global(metric) => {
+                let (graph, owned) = routing::topology(metric).await; 
+                Some(routing::prefix_table(&routing::shortest_paths(&graph, self.ipv4addr), &owned))
+            }, 
+            Mode::DistanceVector => Some(self.dv.lock().await.table(&routing::dv_config())), 
+            Mode::LinkState(_) => Some(self.lsdb.lock().await.table(self.ipv4addr)), 
+        }
+    }
+
+    /// replace the dynamic routes by computed ones, see `routing::recompute`; static routes are kept. 
+    pub async fn install_routes(&self, table: Paths<Prefix>) {
+        let mut routers = self.routers.lock().await; 
+        let origin_items = routers.len(); 
+        routers.retain(|_, r| r.is_static); 
+        for (target, (metric, next_hops)) in table {
+            routers.entry(target).or_insert(Route::dynamic(metric, next_hops)); 
+        }
+        let new_item_len = routers.len(); 
+        drop(routers); 
+        if cfg!(feature = "log-update") {
+            eprintln!("\x1b[32;1m[{:21}] router: {}, table size {} -> {}. \x1b[0m", "Router Table Install", self.ipv4addr, origin_items, new_item_len)
+        }
+    }
+
+    pub async fn update_routes(&self) {
+        match routing::mode() {
+            Mode::Periodic => {}, 
+            Mode::DistanceVector => return self.advertise_vectors().await, 
+            Mode::LinkState(_) => return self.originate_lsa().await, 
+            Mode::Global(_) => return, 
+        }
+        let own = self.prefixes().await; 
+        let globals = GLOBAL_ROUTERS.lock().await; 
+        let mut routers = self.routers.lock().await; 
+        let origin_items = routers.len(); 
+        routers.retain(|_, r| r.is_static); 
+        {
+            let outer = self.outers.lock().await; 
+            for (t, link) in outer.iter() {
+                let bw = link.bandwidth; 
+                if bw == 0 {
+                    continue 
+                }
+                let speed = 1. / bw as f64; 
+                // the neighbor itself, and the subnets it owns. 
+                let mut prefixes = vec![Prefix::host(*t)]; 
+                if let Some(g3) = globals.get(t) {
+                    prefixes.extend(g3.subnets.lock().await.iter().copied()); 
                 }
-                drop(globals); 
-                // calculate the end... 
-                let new_item_len = routers.len(); 
-                drop(routers); 
-                if cfg!(feature = "log-update") {
-                    eprintln!("\x1b[32;1m[{:21}] {}\x1b[0m", "Router Table Update", 
-                        if origin_items == new_item_len {
-                            format!("table size({origin_items}) not changed. ")
-                        } else {
-                            format!("table size {} -> {}. ", origin_items, new_item_len)
-                        })
+                for prefix in prefixes {
+                    routers.entry(prefix).and_modify(|v| v.offer(speed, *t)).or_insert(Route::dynamic(speed, vec![*t])); 
                 }
-                // update your last update time! 
-                last_instant = now; 
             }
-            yield_now().await; 
-        }; 
+        }
+        let p: Vec<_> = self.outers.lock().await.iter().map(|(ipv4, link)| (*ipv4, link.bandwidth)).collect(); 
+        for (ip, bw) in p {
+            let speed = 1. / bw as f64; 
+            if bw == 0 { continue }
+            if let Some(g3) = globals.get(&ip) {
+                let r2 = g3.routers.lock().await;
+                for (target, r) in r2.iter() {
+                    if own.contains(target) { continue }
+                    let entry = routers.entry(*target); 
+                    let speed = speed + r.metric; 
+                    entry.and_modify(|v| v.offer(speed, g3.ipv4addr)).or_insert(Route::dynamic(speed, vec![g3.ipv4addr])); 
+                }
+            }
+        }
+        drop(globals); 
+        // calculate the end... 
+        let new_item_len = routers.len(); 
+        drop(routers); 
+        if cfg!(feature = "log-update") {
+            eprintln!("\x1b[32;1m[{:21}] {}\x1b[0m", "Router Table Update", 
+                if origin_items == new_item_len {
+                    format!("table size({origin_items}) not changed. ")
+                } else {
+                    format!("table size {} -> {}. ", origin_items, new_item_len)
+                })
+        }
     }
 }
 
 pub mod config {
     
-    use std::sync::atomic::AtomicUsize;
+    use std::{collections::BTreeMap, sync::{Mutex, atomic::{AtomicBool, AtomicUsize, AtomicU64}}};
 
     use std::sync::atomic::Ordering::Relaxed;
 
@@ -236,6 +1010,30 @@ pub mod config {
     pub static LOSS_BYTES: AtomicUsize = AtomicUsize::new(0); 
     pub static RECEIVE_BYTES: AtomicUsize = AtomicUsize::new(0); 
 
+    /// packets dropped for exceeding `MAX_HOPS`, most likely caught in a routing loop. 
+    pub static LOOP_PACKETS: AtomicUsize = AtomicUsize::new(0); 
+
+    /// splits of a packet (or of a fragment again) for the mtu of a link, and packets put together again. 
+    pub static FRAGMENTED_PACKETS: AtomicUsize = AtomicUsize::new(0); 
+    pub static REASSEMBLED_PACKETS: AtomicUsize = AtomicUsize::new(0); 
+
+    /// whether a router splits a packet over the mtu of a link, or drops it with an error, set by the 
+    /// FRAGMENT command. 
+    pub static FRAGMENT: AtomicBool = AtomicBool::new(false); 
+
+    /// whether a router reports the packets it cannot route back to their source, set by the ERRORS command. 
+    pub static ERRORS: AtomicBool = AtomicBool::new(false); 
+
+    /// the priority of the packets to a destination port, set by the CLASSIFY command, a priority 
+    /// asked by the client goes first. 
+    pub static PORT_CLASSES: Mutex<BTreeMap<u16, u8>> = Mutex::new(BTreeMap::new()); 
+
+    /// the hop limit of the packets, set by the TTL command, a client may ask for a lower one. 
+    pub static MAX_HOPS: AtomicUsize = AtomicUsize::new(64); 
+
+    /// seed of all the random behaviors, each router derives its own stream from it. 
+    pub static SEED: AtomicU64 = AtomicU64::new(0); 
+
     pub async fn drop_packet(input: usize, hint: &str, packet: MessageType) {
         if input < 6 {
             // impossible, without the proper bytes ahead... 
@@ -249,4 +1047,127 @@ pub mod config {
         }
         CACHES.lock().await.push_back(packet); 
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*; 
+
+    /// a data packet from `source` to `target` with `payload`. 
+    fn data(source: SocketAddrV4, target: Ipv4Addr, payload: &[u8]) -> Message {
+        let mut message = Box::new([0; MESSAGE_LENGTH]); 
+        message[..4].copy_from_slice(&source.ip().octets()); 
+        message[4..HEADER_LENGTH].copy_from_slice(&source.port().to_le_bytes()); 
+        message[HEADER_LENGTH..HEADER_LENGTH + payload.len()].copy_from_slice(payload); 
+        Message { target: SocketAddrV4::new(target, 9000), message, message_len: HEADER_LENGTH + payload.len(), kind: MessageKind::Data, 
+            hops: 0, hop_limit: 64, enqueued: Duration::ZERO, priority: 0, ecn: Ecn::NotCapable, fragment: None }
+    }
+
+    fn payload_of(m: &Message) -> &[u8] {
+        &m.message[HEADER_LENGTH..m.message_len]
+    }
+
+    async fn router(last: u8) -> Arc<Router> {
+        let udp = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap()); 
+        Router::from_ipv4addr(Ipv4Addr::new(127, 0, 25, last), udp).await
+    }
+
+    #[tokio::test]
+    async fn fragments_reassemble_in_any_order() {
+        let r = router(1).await; 
+        let source = SocketAddrV4::new(Ipv4Addr::new(127, 0, 25, 9), 1); 
+        let payload: Vec<u8> = (0..250).map(|i| i as u8).collect(); 
+        let pieces = r.fragment(data(source, r.ipv4addr, &payload), HEADER_LENGTH + 100).await; 
+        assert_eq!(pieces.iter().map(|p| (p.fragment.unwrap().offset, p.fragment.unwrap().more)).collect::<Vec<_>>(), 
+            vec![(0, true), (100, true), (200, false)]); 
+        let mut whole = None; 
+        for p in pieces {
+            assert!(whole.is_none()); 
+            whole = r.reassemble(p).await; 
+        }
+        let whole = whole.unwrap(); 
+        assert_eq!((payload_of(&whole), whole.fragment), (&payload[..], None)); 
+        // out of order, the middle fragment split again on a smaller mtu. 
+        let mut pieces = r.fragment(data(source, r.ipv4addr, &payload), HEADER_LENGTH + 100).await; 
+        let middle = pieces.remove(1); 
+        let split = r.fragment(middle, HEADER_LENGTH + 40).await; 
+        assert_eq!(split.iter().map(|p| (p.fragment.unwrap().offset, p.fragment.unwrap().more)).collect::<Vec<_>>(), 
+            vec![(100, true), (140, true), (180, true)]); 
+        let mut order: Vec<Message> = pieces.into_iter().rev().chain(split.into_iter().rev()).collect(); 
+        let last = order.pop().unwrap(); 
+        for p in order {
+            assert!(r.reassemble(p).await.is_none()); 
+        }
+        let whole = r.reassemble(last).await.unwrap(); 
+        assert_eq!(payload_of(&whole), &payload[..]); 
+    }
+
+    #[tokio::test]
+    async fn duplicate_fragments_are_dropped() {
+        let r = router(2).await; 
+        let source = SocketAddrV4::new(Ipv4Addr::new(127, 0, 25, 9), 1); 
+        let payload: Vec<u8> = (0..250).map(|i| i as u8).collect(); 
+        let pieces = r.fragment(data(source, r.ipv4addr, &payload), HEADER_LENGTH + 100).await; 
+        let copy = |p: &Message| Message { message: p.message.clone(), ..*p }; 
+        let late = copy(&pieces[1]); 
+        assert!(r.reassemble(copy(&pieces[0])).await.is_none()); 
+        let mut whole = None; 
+        for p in pieces {
+            whole = r.reassemble(p).await; 
+        }
+        assert_eq!(payload_of(&whole.unwrap()), &payload[..]); 
+        // the packet is put together, a late copy starts no new one. 
+        assert!(r.reassemble(late).await.is_none()); 
+        assert!(r.reassembly.lock().await.pending.is_empty()); 
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn expired_fragments_report_only_the_first() {
+        let r = router(3).await; 
+        let source = SocketAddrV4::new(Ipv4Addr::new(127, 0, 25, 9), 1); 
+        // the errors to the source go over a link the test listens to. 
+        let (sender, mut errors) = mpsc::unbounded_channel(); 
+        let neighbor = Ipv4Addr::new(127, 0, 25, 4); 
+        r.outers().lock().await.insert(neighbor, Link::new(1_000_000_000, QueueLimit::packets(5), sender)); 
+        r.add_static_route(Prefix::host(*source.ip()), vec![neighbor]).await; 
+        config::ERRORS.store(true, Relaxed); 
+        let payload = [7; 250]; 
+        let mut pieces = r.fragment(data(source, r.ipv4addr, &payload), HEADER_LENGTH + 100).await; 
+        pieces.remove(1); 
+        for p in pieces {
+            assert!(r.reassemble(p).await.is_none()); 
+        }
+        tokio::time::advance(REASSEMBLY_TIMEOUT * 2).await; 
+        r.expire_fragments().await; 
+        assert!(r.reassembly.lock().await.pending.is_empty()); 
+        let error = tokio::time::timeout(Duration::from_secs(1), errors.recv()).await.unwrap().unwrap(); 
+        assert_eq!((error.kind, error.target), (MessageKind::Error, source)); 
+        assert!(tokio::time::timeout(Duration::from_secs(1), errors.recv()).await.is_err()); 
+    }
+
+    #[tokio::test]
+    async fn computed_route_returns_after_route_del() {
+        let udp = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap()); 
+        let ip = |last| Ipv4Addr::new(127, 0, 12, last); 
+        let mut routers = Vec::new(); 
+        for last in 1..=3 {
+            routers.push(Router::from_ipv4addr(ip(last), udp.clone()).await); 
+        }
+        // a line 1 - 2 - 3. 
+        for (a, b) in [(0, 1), (1, 0), (1, 2), (2, 1)] {
+            let link = Link::new(1000, QueueLimit::packets(5), routers[b].sender().clone()); 
+            routers[a].outers().lock().await.insert(ip(b as u8 + 1), link); 
+        }
+        routing::set_mode(Mode::Global(routing::Metric::Hops)); 
+        routing::recompute().await; 
+        let route = |r: &Arc<Router>| {
+            let r = r.clone(); 
+            async move { r.routers.lock().await.get(&Prefix::host(ip(3))).map(|r| (r.is_static, r.next_hops.clone())) }
+        }; 
+        assert_eq!(route(&routers[0]).await, Some((false, vec![ip(2)]))); 
+        routers[0].add_static_route(Prefix::host(ip(3)), vec![ip(2)]).await; 
+        assert_eq!(route(&routers[0]).await, Some((true, vec![ip(2)]))); 
+        assert!(routers[0].remove_static_route(Prefix::host(ip(3))).await); 
+        assert_eq!(route(&routers[0]).await, Some((false, vec![ip(2)]))); 
+        assert!(!routers[0].remove_static_route(Prefix::host(ip(3))).await); 
+    }
+}
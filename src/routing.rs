@@ -0,0 +1,593 @@
+//! How the routing tables are filled: the legacy periodic neighbor peek in `Router::update_routes`,
+//! a shortest path computation over the whole topology whenever it changes, or a distance-vector
+//! or link-state protocol whose advertisements travel on the simulated links.
+
+use std::{collections::{BTreeMap, BTreeSet}, fmt, net::Ipv4Addr, str::FromStr, sync::Mutex, time::Duration}; 
+
+use crate::router::{Link, GLOBAL_ROUTERS, PERIOD_UPDATE}; 
+
+/// An ipv4 prefix such as `127.4.0.0/16`, the key of the routing tables.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Prefix {
+    addr: Ipv4Addr, 
+    len: u8, 
+}
+
+impl Prefix {
+    pub const DEFAULT: Prefix = Prefix { addr: Ipv4Addr::UNSPECIFIED, len: 0 }; 
+
+    /// the host bits of `addr` are cleared, `len` is at most 32.
+    pub fn new(addr: Ipv4Addr, len: u8) -> Prefix {
+        let len = len.min(32); 
+        Prefix { addr: Ipv4Addr::from(u32::from(addr) & Prefix::mask(len)), len }
+    }
+
+    pub const fn host(addr: Ipv4Addr) -> Prefix {
+        Prefix { addr, len: 32 }
+    }
+
+    fn mask(len: u8) -> u32 {
+        u32::MAX.checked_shl(32 - len as u32).unwrap_or(0)
+    }
+
+    pub const fn addr(&self) -> Ipv4Addr {
+        self.addr
+    }
+
+    pub const fn prefix_len(&self) -> u8 {
+        self.len
+    }
+
+    pub fn contains(&self, ip: Ipv4Addr) -> bool {
+        u32::from(ip) & Prefix::mask(self.len) == u32::from(self.addr)
+    }
+}
+
+impl FromStr for Prefix {
+    type Err = (); 
+
+    /// `a.b.c.d/n`, or a single address `a.b.c.d` as a /32.
+    fn from_str(s: &str) -> Result<Prefix, ()> {
+        match s.split_once('/') {
+            Some((addr, len)) => match (Ipv4Addr::from_str(addr), len.parse::<u8>()) {
+                (Ok(addr), Ok(len)) if len <= 32 => Ok(Prefix::new(addr, len)), 
+                _ => Err(()), 
+            }, 
+            None => Ipv4Addr::from_str(s).map(Prefix::host).map_err(|_| ()), 
+        }
+    }
+}
+
+impl fmt::Display for Prefix {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.len)
+    }
+}
+
+/// the entry of the longest prefix containing `ip`.
+pub fn longest_match<V>(table: &BTreeMap<Prefix, V>, ip: Ipv4Addr) -> Option<&V> {
+    (0..=32).rev().find_map(|len| table.get(&Prefix::new(ip, len)))
+}
+
+/// cost and next hops of a route, several next hops have an equal cost.
+pub type Paths<K> = BTreeMap<K, (f64, Vec<Ipv4Addr>)>; 
+
+/// whether two path costs are equal, up to the rounding of the sums.
+pub fn same_cost(a: f64, b: f64) -> bool {
+    (a - b).abs() <= 1e-9 * a.abs().max(b.abs())
+}
+
+/// keep the cheaper of two routes, or both next hops when they cost the same.
+fn merge(e: &mut (f64, Vec<Ipv4Addr>), cost: f64, hops: &[Ipv4Addr]) {
+    if same_cost(cost, e.0) {
+        for h in hops {
+            if !e.1.contains(h) {
+                e.1.push(*h); 
+            }
+        }
+        e.1.sort(); 
+    } else if cost < e.0 {
+        *e = (cost, hops.to_vec()); 
+    }
+}
+
+/// turn the paths towards routers into routes towards the prefixes they own.
+pub fn prefix_table(paths: &Paths<Ipv4Addr>, owned: &BTreeMap<Ipv4Addr, Vec<Prefix>>) -> Paths<Prefix> {
+    let mut table: Paths<Prefix> = BTreeMap::new(); 
+    for (router, (cost, hops)) in paths.iter() {
+        let prefixes = owned.get(router).into_iter().flatten().copied(); 
+        for prefix in std::iter::once(Prefix::host(*router)).chain(prefixes) {
+            // a prefix owned by several routers goes to the nearest ones.
+            table.entry(prefix).and_modify(|e| merge(e, *cost, hops)).or_insert((*cost, hops.clone())); 
+        }
+    }
+    table
+}
+
+/// The cost of one link for the shortest path computation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Metric {
+    Hops, 
+    /// 1 / bandwidth, a slow link costs more.
+    Bandwidth, 
+    /// propagation delay in seconds, plus `HOP_COST`.
+    Delay, 
+}
+
+/// the cost every link adds under `Metric::Delay`. a link without delay would cost 0 otherwise, and
+/// the equal-cost next hops through it could send a packet back and forth.
+pub const HOP_COST: f64 = 1e-6; 
+
+impl Metric {
+    /// strictly positive, so the next hops of a route always get closer to the target.
+    pub fn cost(&self, link: &Link) -> f64 {
+        match self {
+            Metric::Hops => 1., 
+            Metric::Bandwidth => 1. / link.bandwidth as f64, 
+            Metric::Delay => link.delay.as_secs_f64() + HOP_COST, 
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Metric> {
+        match s {
+            "HOPS" => Some(Metric::Hops), 
+            "BANDWIDTH" => Some(Metric::Bandwidth), 
+            "DELAY" => Some(Metric::Delay), 
+            _ => None, 
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// every router peeks the tables of its neighbors each `PERIOD_UPDATE`.
+    Periodic, 
+    /// all the tables are computed at once from `GLOBAL_ROUTERS` on each topology change.
+    Global(Metric), 
+    /// RIP-like hop count vectors sent to the neighbors each `DV_PERIOD`.
+    DistanceVector, 
+    /// OSPF-like advertisements flooded to all the routers, each runs SPF on its own database.
+    LinkState(Metric), 
+}
+
+static MODE: Mutex<Mode> = Mutex::new(Mode::Periodic); 
+
+pub fn mode() -> Mode {
+    *MODE.lock().unwrap()
+}
+
+pub fn set_mode(mode: Mode) {
+    *MODE.lock().unwrap() = mode; 
+}
+
+/// the interval of the periodic routing work in the current mode.
+pub fn period() -> Duration {
+    match mode() {
+        Mode::DistanceVector => DV_PERIOD, 
+        Mode::LinkState(_) => LS_PERIOD, 
+        _ => PERIOD_UPDATE, 
+    }
+}
+
+/// neighbors of each router and the cost to reach them.
+pub type Graph = BTreeMap<Ipv4Addr, Vec<(Ipv4Addr, f64)>>; 
+
+/// snapshot of the links of all the routers, and of the subnets they own.
+pub async fn topology(metric: Metric) -> (Graph, BTreeMap<Ipv4Addr, Vec<Prefix>>) {
+    let globals = GLOBAL_ROUTERS.lock().await; 
+    let mut graph = Graph::new(); 
+    let mut owned = BTreeMap::new(); 
+    for (ip, router) in globals.iter() {
+        let outers = router.outers().lock().await; 
+        graph.insert(*ip, outers.iter().map(|(t, link)| (*t, metric.cost(link))).collect()); 
+        owned.insert(*ip, router.subnets().lock().await.clone()); 
+    }
+    (graph, owned)
+}
+
+/// Dijkstra from `source`, returns the cost and the first hops (all of the equal-cost paths) towards
+/// every reachable router.
+pub fn shortest_paths(graph: &Graph, source: Ipv4Addr) -> Paths<Ipv4Addr> {
+    let mut dist: Paths<Ipv4Addr> = BTreeMap::new(); 
+    let mut done = BTreeSet::new(); 
+    let mut current = Some((source, 0., vec![])); 
+    while let Some((u, du, first)) = current {
+        done.insert(u); 
+        for (v, c) in graph.get(&u).into_iter().flatten() {
+            if done.contains(v) {
+                continue
+            }
+            // leaving the source, the first hop is the neighbor itself.
+            let hops = if u == source { vec![*v] } else { first.clone() }; 
+            let d = du + c; 
+            dist.entry(*v).and_modify(|e| merge(e, d, &hops)).or_insert((d, hops)); 
+        }
+        current = dist.iter()
+            .filter(|(v, _)| !done.contains(*v))
+            .min_by(|a, b| a.1.0.total_cmp(&b.1.0))
+            .map(|(v, (d, hops))| (*v, *d, hops.clone())); 
+    }
+    dist.remove(&source); 
+    dist
+}
+
+/// in the global mode, recompute and install the tables of all the routers;
+/// in the link-state mode, every router floods a fresh advertisement of its links instead.
+pub async fn recompute() {
+    let routers: Vec<_> = GLOBAL_ROUTERS.lock().await.values().cloned().collect(); 
+    match mode() {
+        Mode::Global(metric) => {
+            let (graph, owned) = topology(metric).await; 
+            for r in routers {
+                let table = prefix_table(&shortest_paths(&graph, r.ipv4addr()), &owned); 
+                r.install_routes(table).await; 
+            }
+        }, 
+        Mode::LinkState(_) => {
+            for r in routers {
+                r.originate_lsa().await; 
+            }
+        }, 
+        _ => {}, 
+    }
+}
+
+pub const DV_PERIOD: Duration = Duration::from_secs(5); 
+/// a route not refreshed for this long becomes unreachable, and is removed after the same time again.
+pub const DV_TIMEOUT: Duration = Duration::from_secs(30); 
+
+#[derive(Debug, Clone, Copy)]
+pub struct DvConfig {
+    /// do not advertise a route back to the neighbor it is learnt from.
+    pub split_horizon: bool, 
+    /// advertise it back with `infinity` instead, takes precedence over split horizon.
+    pub poison_reverse: bool, 
+    /// the metric meaning unreachable.
+    pub infinity: u16, 
+}
+
+static DV_CONFIG: Mutex<DvConfig> = Mutex::new(DvConfig { split_horizon: true, poison_reverse: false, infinity: 16 }); 
+
+pub fn dv_config() -> DvConfig {
+    *DV_CONFIG.lock().unwrap()
+}
+
+pub fn set_dv_config(config: DvConfig) {
+    *DV_CONFIG.lock().unwrap() = config; 
+}
+
+#[derive(Debug)]
+struct DvRoute {
+    metric: u16, 
+    next_hop: Ipv4Addr, 
+    refreshed: Duration, 
+}
+
+/// The routes a router learns from the vectors of its neighbors.
+#[derive(Debug, Default)]
+pub struct DistanceVector {
+    routes: BTreeMap<Prefix, DvRoute>, 
+}
+
+impl DistanceVector {
+    /// merge the vector of neighbor `from` received at `now`, returns whether a metric changed.
+    pub fn receive(&mut self, own: &[Prefix], from: Ipv4Addr, vector: &[(Prefix, u16)], now: Duration, config: &DvConfig) -> bool {
+        let mut changed = false; 
+        for &(dest, metric) in vector {
+            if own.contains(&dest) {
+                continue
+            }
+            let metric = metric.saturating_add(1).min(config.infinity); 
+            match self.routes.get_mut(&dest) {
+                // the current next hop is always believed, even when it gets worse.
+                Some(r) if r.next_hop == from => {
+                    changed |= r.metric != metric; 
+                    r.metric = metric; 
+                    r.refreshed = now; 
+                }, 
+                Some(r) if metric < r.metric => {
+                    *r = DvRoute { metric, next_hop: from, refreshed: now }; 
+                    changed = true; 
+                }, 
+                Some(_) => {}, 
+                None if metric < config.infinity => {
+                    self.routes.insert(dest, DvRoute { metric, next_hop: from, refreshed: now }); 
+                    changed = true; 
+                }, 
+                None => {}, 
+            }
+        }
+        changed
+    }
+
+    /// time out the routes which are not refreshed, returns whether a metric changed.
+    pub fn expire(&mut self, now: Duration, config: &DvConfig) -> bool {
+        let mut changed = false; 
+        self.routes.retain(|_, r| {
+            let age = now.saturating_sub(r.refreshed); 
+            if r.metric >= config.infinity {
+                return age < DV_TIMEOUT * 2
+            }
+            if age >= DV_TIMEOUT {
+                r.metric = config.infinity; 
+                changed = true; 
+            }
+            true
+        }); 
+        changed
+    }
+
+    /// the vector sent to `neighbor`, with the own prefixes at metric 0.
+    pub fn advertisement(&self, own: &[Prefix], neighbor: Ipv4Addr, config: &DvConfig) -> Vec<(Prefix, u16)> {
+        let mut vector: Vec<(Prefix, u16)> = own.iter().map(|p| (*p, 0)).collect(); 
+        for (dest, r) in self.routes.iter() {
+            let metric = if r.next_hop != neighbor {
+                r.metric.min(config.infinity)
+            } else if config.poison_reverse {
+                config.infinity
+            } else if config.split_horizon {
+                continue
+            } else {
+                r.metric.min(config.infinity)
+            }; 
+            vector.push((*dest, metric)); 
+        }
+        vector
+    }
+
+    /// the reachable routes, as a routing table.
+    pub fn table(&self, config: &DvConfig) -> Paths<Prefix> {
+        self.routes.iter()
+            .filter(|(_, r)| r.metric < config.infinity)
+            .map(|(dest, r)| (*dest, (r.metric as f64, vec![r.next_hop])))
+            .collect()
+    }
+}
+
+/// write the vector as (ipv4, prefix length, metric in little endian) items of 7 bytes, returns the
+/// bytes written.
+pub fn encode_vector(vector: &[(Prefix, u16)], buffer: &mut [u8]) -> usize {
+    let mut len = 0; 
+    for (prefix, metric) in vector.iter().take(buffer.len() / 7) {
+        buffer[len..len + 4].copy_from_slice(&prefix.addr().octets()); 
+        buffer[len + 4] = prefix.prefix_len(); 
+        buffer[len + 5..len + 7].copy_from_slice(&metric.to_le_bytes()); 
+        len += 7; 
+    }
+    len
+}
+
+pub fn decode_vector(buffer: &[u8]) -> Vec<(Prefix, u16)> {
+    buffer.chunks_exact(7)
+        .map(|c| (Prefix::new(Ipv4Addr::new(c[0], c[1], c[2], c[3]), c[4]), u16::from_le_bytes([c[5], c[6]])))
+        .collect()
+}
+
+pub const LS_PERIOD: Duration = Duration::from_secs(5); 
+/// an advertisement not refreshed by its origin for this long is removed from the databases.
+pub const LS_MAX_AGE: Duration = Duration::from_secs(30); 
+/// the age a copy gains at each hop it is flooded over, as `InfTransDelay` of OSPF.
+pub const LS_TRANSIT_AGE: Duration = Duration::from_secs(1); 
+
+/// Link-state advertisement: the links of `origin` and their costs, and the subnets it owns.
+#[derive(Debug, Clone)]
+pub struct Lsa {
+    pub origin: Ipv4Addr, 
+    pub seq: u32, 
+    pub age: Duration, 
+    pub links: Vec<(Ipv4Addr, f64)>, 
+    pub subnets: Vec<Prefix>, 
+}
+
+impl Lsa {
+    /// origin (4 bytes), seq (4), age in ms (4), link count (2), subnet count (2), then ipv4 (4) and
+    /// cost (8) per link, and ipv4 (4) and prefix length (1) per subnet, all in little endian; returns
+    /// the bytes written.
+    pub fn encode(&self, buffer: &mut [u8]) -> usize {
+        let links = self.links.len().min((buffer.len() - 16) / 12); 
+        let subnets = self.subnets.len().min((buffer.len() - 16 - links * 12) / 5); 
+        buffer[..4].copy_from_slice(&self.origin.octets()); 
+        buffer[4..8].copy_from_slice(&self.seq.to_le_bytes()); 
+        buffer[8..12].copy_from_slice(&(self.age.as_millis() as u32).to_le_bytes()); 
+        buffer[12..14].copy_from_slice(&(links as u16).to_le_bytes()); 
+        buffer[14..16].copy_from_slice(&(subnets as u16).to_le_bytes()); 
+        let mut len = 16; 
+        for (ip, cost) in self.links.iter().take(links) {
+            buffer[len..len + 4].copy_from_slice(&ip.octets()); 
+            buffer[len + 4..len + 12].copy_from_slice(&cost.to_le_bytes()); 
+            len += 12; 
+        }
+        for prefix in self.subnets.iter().take(subnets) {
+            buffer[len..len + 4].copy_from_slice(&prefix.addr().octets()); 
+            buffer[len + 4] = prefix.prefix_len(); 
+            len += 5; 
+        }
+        len
+    }
+
+    pub fn decode(buffer: &[u8]) -> Option<Lsa> {
+        let head = buffer.get(..16)?; 
+        let origin = Ipv4Addr::new(head[0], head[1], head[2], head[3]); 
+        let seq = u32::from_le_bytes(head[4..8].try_into().unwrap()); 
+        let age = Duration::from_millis(u32::from_le_bytes(head[8..12].try_into().unwrap()) as u64); 
+        let count = u16::from_le_bytes([head[12], head[13]]) as usize; 
+        let subnet_count = u16::from_le_bytes([head[14], head[15]]) as usize; 
+        let links = buffer[16..].chunks_exact(12).take(count)
+            .map(|c| (Ipv4Addr::new(c[0], c[1], c[2], c[3]), f64::from_le_bytes(c[4..12].try_into().unwrap())))
+            .collect::<Vec<_>>(); 
+        let subnets = buffer.get(16 + count * 12..)?.chunks_exact(5).take(subnet_count)
+            .map(|c| Prefix::new(Ipv4Addr::new(c[0], c[1], c[2], c[3]), c[4]))
+            .collect::<Vec<_>>(); 
+        (links.len() == count && subnets.len() == subnet_count).then_some(Lsa { origin, seq, age, links, subnets })
+    }
+}
+
+/// The advertisements known by one router, with the time each is received.
+#[derive(Debug, Default)]
+pub struct LinkStateDb {
+    lsas: BTreeMap<Ipv4Addr, (Lsa, Duration)>, 
+    /// the sequence number of the own advertisement.
+    seq: u32, 
+}
+
+impl LinkStateDb {
+    /// store the advertisement if it is newer than the known one, returns whether it is stored.
+    pub fn receive(&mut self, lsa: Lsa, now: Duration) -> bool {
+        if lsa.age >= LS_MAX_AGE {
+            return false
+        }
+        match self.lsas.get(&lsa.origin) {
+            Some((known, _)) if known.seq >= lsa.seq => false, 
+            _ => {
+                self.lsas.insert(lsa.origin, (lsa, now)); 
+                true
+            }, 
+        }
+    }
+
+    /// a new advertisement of the own links.
+    pub fn originate(&mut self, me: Ipv4Addr, links: Vec<(Ipv4Addr, f64)>, subnets: Vec<Prefix>, now: Duration) -> Lsa {
+        self.seq += 1; 
+        let lsa = Lsa { origin: me, seq: self.seq, age: Duration::ZERO, links, subnets }; 
+        self.lsas.insert(me, (lsa.clone(), now)); 
+        lsa
+    }
+
+    /// the stored advertisement of `origin`, aged by the time it is kept here.
+    pub fn get(&self, origin: Ipv4Addr, now: Duration) -> Option<Lsa> {
+        self.lsas.get(&origin).map(|(lsa, received)| Lsa { age: lsa.age + now.saturating_sub(*received), ..lsa.clone() })
+    }
+
+    /// remove the advertisements reaching `LS_MAX_AGE`, returns whether any is removed.
+    pub fn expire(&mut self, now: Duration) -> bool {
+        let before = self.lsas.len(); 
+        self.lsas.retain(|_, (lsa, received)| lsa.age + now.saturating_sub(*received) < LS_MAX_AGE); 
+        before != self.lsas.len()
+    }
+
+    pub fn graph(&self) -> Graph {
+        self.lsas.iter().map(|(origin, (lsa, _))| (*origin, lsa.links.clone())).collect()
+    }
+
+    pub fn subnets(&self) -> BTreeMap<Ipv4Addr, Vec<Prefix>> {
+        self.lsas.iter().map(|(origin, (lsa, _))| (*origin, lsa.subnets.clone())).collect()
+    }
+
+    /// SPF from `me` over the database, as a routing table.
+    pub fn table(&self, me: Ipv4Addr) -> Paths<Prefix> {
+        prefix_table(&shortest_paths(&self.graph(), me), &self.subnets())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::mpsc; 
+
+    use crate::queue::QueueLimit; 
+
+    use super::*; 
+
+    fn ip(last: u8) -> Ipv4Addr {
+        Ipv4Addr::new(127, 0, 0, last)
+    }
+
+    /// links in both directions, all of the same cost.
+    fn graph(edges: &[(u8, u8)], cost: f64) -> Graph {
+        let mut graph = Graph::new(); 
+        for &(a, b) in edges {
+            graph.entry(ip(a)).or_default().push((ip(b), cost)); 
+            graph.entry(ip(b)).or_default().push((ip(a), cost)); 
+        }
+        graph
+    }
+
+    #[test]
+    fn prefix_parse_and_longest_match() {
+        assert_eq!("127.4.1.9/16".parse::<Prefix>(), Ok(Prefix::new(Ipv4Addr::new(127, 4, 0, 0), 16))); 
+        assert_eq!("127.0.0.1".parse::<Prefix>(), Ok(Prefix::host(ip(1)))); 
+        assert!("127.0.0.1/33".parse::<Prefix>().is_err()); 
+        let mut table = BTreeMap::new(); 
+        table.insert(Prefix::DEFAULT, "default"); 
+        table.insert("127.4.0.0/16".parse().unwrap(), "wide"); 
+        table.insert("127.4.8.0/24".parse().unwrap(), "narrow"); 
+        assert_eq!(longest_match(&table, Ipv4Addr::new(127, 4, 8, 1)), Some(&"narrow")); 
+        assert_eq!(longest_match(&table, Ipv4Addr::new(127, 4, 9, 1)), Some(&"wide")); 
+        assert_eq!(longest_match(&table, Ipv4Addr::new(10, 0, 0, 1)), Some(&"default")); 
+    }
+
+    #[test]
+    fn equal_cost_paths_keep_every_first_hop() {
+        // 1 - 2 - 4 and 1 - 3 - 4
+        let paths = shortest_paths(&graph(&[(1, 2), (1, 3), (2, 4), (3, 4)], 1.), ip(1)); 
+        assert_eq!(paths[&ip(4)], (2., vec![ip(2), ip(3)])); 
+        assert_eq!(paths[&ip(2)], (1., vec![ip(2)])); 
+    }
+
+    #[test]
+    fn undelayed_links_do_not_loop() {
+        let (sender, _receiver) = mpsc::unbounded_channel(); 
+        let link = Link::new(1000, QueueLimit::packets(5), sender); 
+        let cost = Metric::Delay.cost(&link); 
+        assert!(cost > 0.); 
+        // a triangle: every router reaches the others directly, never through the third one.
+        let graph = graph(&[(1, 2), (2, 3), (1, 3)], cost); 
+        for me in 1..=3 {
+            let paths = shortest_paths(&graph, ip(me)); 
+            for (target, (_, hops)) in paths.iter() {
+                assert_eq!(hops, &vec![*target]); 
+            }
+        }
+    }
+
+    #[test]
+    fn distance_vector_split_horizon_and_poison_reverse() {
+        let own = [Prefix::host(ip(1))]; 
+        let mut config = DvConfig { split_horizon: true, poison_reverse: false, infinity: 16 }; 
+        let mut dv = DistanceVector::default(); 
+        assert!(dv.receive(&own, ip(2), &[(Prefix::host(ip(2)), 0), (Prefix::host(ip(3)), 1), (Prefix::host(ip(1)), 1)], Duration::ZERO, &config)); 
+        assert_eq!(dv.table(&config)[&Prefix::host(ip(3))], (2., vec![ip(2)])); 
+        // the routes through 2 are not told back to 2, but to the others.
+        assert_eq!(dv.advertisement(&own, ip(2), &config), vec![(Prefix::host(ip(1)), 0)]); 
+        assert!(dv.advertisement(&own, ip(4), &config).contains(&(Prefix::host(ip(3)), 2))); 
+        config.poison_reverse = true; 
+        assert!(dv.advertisement(&own, ip(2), &config).contains(&(Prefix::host(ip(3)), 16))); 
+        // the next hop is believed when the route gets worse, until it is unreachable.
+        assert!(dv.receive(&own, ip(2), &[(Prefix::host(ip(3)), 16)], Duration::ZERO, &config)); 
+        assert!(!dv.table(&config).contains_key(&Prefix::host(ip(3)))); 
+    }
+
+    #[test]
+    fn distance_vector_expires_stale_routes() {
+        let config = dv_config(); 
+        let mut dv = DistanceVector::default(); 
+        dv.receive(&[], ip(2), &[(Prefix::host(ip(2)), 0)], Duration::ZERO, &config); 
+        assert!(!dv.expire(DV_TIMEOUT / 2, &config)); 
+        assert!(dv.expire(DV_TIMEOUT, &config)); 
+        assert!(dv.table(&config).is_empty()); 
+    }
+
+    #[test]
+    fn lsa_round_trip() {
+        let lsa = Lsa { origin: ip(1), seq: 7, age: Duration::from_millis(1500), links: vec![(ip(2), 1.), (ip(3), 0.25)], 
+            subnets: vec!["127.9.0.0/16".parse().unwrap()] }; 
+        let mut buffer = [0u8; 100]; 
+        let len = lsa.encode(&mut buffer); 
+        let decoded = Lsa::decode(&buffer[..len]).unwrap(); 
+        assert_eq!((decoded.origin, decoded.seq, decoded.age), (lsa.origin, lsa.seq, lsa.age)); 
+        assert_eq!(decoded.links, lsa.links); 
+        assert_eq!(decoded.subnets, lsa.subnets); 
+        assert!(Lsa::decode(&buffer[..len - 1]).is_none()); 
+    }
+
+    #[test]
+    fn link_state_db_keeps_the_newest() {
+        let mut db = LinkStateDb::default(); 
+        let lsa = |seq| Lsa { origin: ip(2), seq, age: Duration::ZERO, links: vec![(ip(1), 1.)], subnets: vec![] }; 
+        assert!(db.receive(lsa(2), Duration::ZERO)); 
+        assert!(!db.receive(lsa(1), Duration::ZERO)); 
+        db.originate(ip(1), vec![(ip(2), 1.)], vec![], Duration::ZERO); 
+        assert_eq!(db.table(ip(1))[&Prefix::host(ip(2))], (1., vec![ip(2)])); 
+        assert_eq!(db.get(ip(2), Duration::from_secs(2)).map(|l| (l.seq, l.age)), Some((2, Duration::from_secs(2)))); 
+        assert!(db.expire(LS_MAX_AGE)); 
+    }
+}
@@ -0,0 +1,97 @@
+//! Token buckets at the ingress routers, they hold each source host to a rate plan. 
+
+use std::time::Duration; 
+
+/// What a bucket does with the packets beyond its rate. 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Conform {
+    /// drop them. 
+    Police, 
+    /// delay them until they conform, but drop those that would wait longer than the bound. 
+    Shape(Duration), 
+}
+
+#[derive(Debug, Clone)]
+pub struct TokenBucket {
+    /// bits per second, like the bandwidth of a link. 
+    pub rate: usize, 
+    /// bytes the bucket holds. 
+    pub burst: usize, 
+    pub conform: Conform, 
+    /// bytes available, below zero while a shaper delays packets. 
+    tokens: f64, 
+    last: Duration, 
+    passed: usize, 
+    delayed: usize, 
+    dropped: usize, 
+}
+
+impl TokenBucket {
+    /// a full bucket at `now`. 
+    pub fn new(rate: usize, burst: usize, conform: Conform, now: Duration) -> TokenBucket {
+        TokenBucket { rate, burst, conform, tokens: burst as f64, last: now, passed: 0, delayed: 0, dropped: 0 }
+    }
+
+    /// take `len` bytes at `now`, returns how long the packet has to wait, or `None` to drop it. 
+    pub fn take(&mut self, len: usize, now: Duration) -> Option<Duration> {
+        let bytes_per_sec = self.rate as f64 / 8.; 
+        let elapsed = now.saturating_sub(self.last).as_secs_f64(); 
+        self.tokens = (self.tokens + elapsed * bytes_per_sec).min(self.burst as f64); 
+        self.last = self.last.max(now); 
+        let len = len as f64; 
+        match self.conform {
+            _ if self.tokens >= len => {
+                self.tokens -= len; 
+                self.passed += 1; 
+                Some(Duration::ZERO)
+            }, 
+            Conform::Police => {
+                self.dropped += 1; 
+                None
+            }, 
+            Conform::Shape(bound) => {
+                // the packet goes once the bucket refills to its size, the packets behind wait longer. 
+                let wait = Duration::from_secs_f64((len - self.tokens) / bytes_per_sec); 
+                if wait > bound {
+                    self.dropped += 1; 
+                    return None
+                }
+                self.tokens -= len; 
+                self.delayed += 1; 
+                Some(wait)
+            }, 
+        }
+    }
+
+    pub fn stats(&self) -> String {
+        format!("{:?} at {} bit/s, burst {} bytes: {} passed, {} delayed, {} dropped", self.conform, self.rate, self.burst, self.passed, self.delayed, self.dropped)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*; 
+
+    const SEC: Duration = Duration::from_secs(1); 
+
+    #[test]
+    fn policer_drops_beyond_the_burst_until_refilled() {
+        // 1000 bytes per second, 1000 bytes of burst.
+        let mut bucket = TokenBucket::new(8000, 1000, Conform::Police, Duration::ZERO); 
+        assert_eq!(bucket.take(600, Duration::ZERO), Some(Duration::ZERO)); 
+        assert_eq!(bucket.take(600, Duration::ZERO), None); 
+        assert_eq!(bucket.take(600, SEC / 2), Some(Duration::ZERO)); 
+        // never more than the burst, however long it is idle.
+        assert_eq!(bucket.take(1001, SEC * 100), None); 
+    }
+
+    #[test]
+    fn shaper_delays_up_to_the_bound() {
+        let mut bucket = TokenBucket::new(8000, 1000, Conform::Shape(SEC), Duration::ZERO); 
+        assert_eq!(bucket.take(1000, Duration::ZERO), Some(Duration::ZERO)); 
+        assert_eq!(bucket.take(500, Duration::ZERO), Some(SEC / 2)); 
+        // behind the delayed one it would wait 1.5 s.
+        assert_eq!(bucket.take(1000, Duration::ZERO), None); 
+        assert_eq!(bucket.take(500, Duration::ZERO), Some(SEC)); 
+    }
+}
@@ -0,0 +1,206 @@
+//! Discrete-event mode: routers are driven by one event queue on a virtual clock instead of
+//! their own `Router::work` tasks, so the same input gives the same run.
+
+use std::{collections::BinaryHeap, cmp::Ordering, net::Ipv4Addr, sync::{Arc, atomic::{AtomicBool, Ordering::Relaxed}}, time::Duration}; 
+
+use tokio::{net::UdpSocket, select, sync::Notify, time::{Instant, sleep_until}}; 
+use lazy_static::lazy_static; 
+
+use crate::{router::{Message, GLOBAL_ROUTERS}, routing}; 
+
+#[derive(Debug)]
+pub enum Event {
+    /// a packet reaches the router, from a client or from a link.
+    Arrival(Ipv4Addr, Message), 
+    /// the router finishes the serialization of the current packet on its link to the neighbor.
+    TransmitDone(Ipv4Addr, Ipv4Addr), 
+    /// the periodic routing table update of the router.
+    RouteUpdate(Ipv4Addr), 
+    /// the oldest incomplete packet of the router may be given up, see `Router::expire_fragments`.
+    ReassemblyTimeout(Ipv4Addr), 
+}
+
+impl Event {
+    /// whether the event belongs to a packet, the periodic ones do not keep a fast run going.
+    const fn is_traffic(&self) -> bool {
+        !matches!(self, Event::RouteUpdate(_))
+    }
+}
+
+struct Scheduled {
+    at: Duration, 
+    seq: u64, 
+    event: Event, 
+}
+
+impl PartialEq for Scheduled {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Scheduled {}
+
+impl PartialOrd for Scheduled {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Scheduled {
+    // reversed, the earliest event (then the first scheduled) is on the top of the heap.
+    fn cmp(&self, other: &Self) -> Ordering {
+        (other.at, other.seq).cmp(&(self.at, self.seq))
+    }
+}
+
+pub struct Simulator {
+    events: BinaryHeap<Scheduled>, 
+    seq: u64, 
+    /// virtual time of the event under processing.
+    now: Duration, 
+    /// `virtual_base` on the virtual clock is `wall_base` on the wall clock.
+    virtual_base: Duration, 
+    wall_base: Instant, 
+    /// jump to the next event while packets are in flight, instead of waiting for the wall clock.
+    fast: bool, 
+    traffic: usize, 
+}
+
+impl Simulator {
+    fn new(fast: bool) -> Simulator {
+        Simulator { events: BinaryHeap::new(), seq: 0, now: Duration::ZERO, virtual_base: Duration::ZERO, wall_base: Instant::now(), fast, traffic: 0 }
+    }
+
+    fn virtual_of(&self, wall: Instant) -> Duration {
+        self.virtual_base + wall.saturating_duration_since(self.wall_base)
+    }
+
+    fn wall_of(&self, at: Duration) -> Instant {
+        self.wall_base + at.saturating_sub(self.virtual_base)
+    }
+
+    fn push(&mut self, at: Duration, event: Event) {
+        if event.is_traffic() {
+            self.traffic += 1; 
+        }
+        self.seq += 1; 
+        self.events.push(Scheduled { at, seq: self.seq, event }); 
+    }
+
+    /// the next event if it is due, otherwise the wall instant to wait for.
+    fn pop(&mut self) -> Option<Result<Event, Instant>> {
+        let next = self.events.peek()?; 
+        let wall = Instant::now(); 
+        if next.at > self.virtual_of(wall) {
+            if !self.fast || self.traffic == 0 {
+                return Some(Err(self.wall_of(next.at)))
+            }
+            self.virtual_base = next.at; 
+            self.wall_base = wall; 
+        }
+        let next = self.events.pop().unwrap(); 
+        if next.event.is_traffic() {
+            self.traffic -= 1; 
+        }
+        self.now = next.at; 
+        Some(Ok(next.event))
+    }
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false); 
+
+lazy_static! {
+    static ref SIMULATOR: std::sync::Mutex<Simulator> = std::sync::Mutex::new(Simulator::new(false)); 
+    static ref WAKE: Notify = Notify::new(); 
+    static ref BOOT: Instant = Instant::now(); 
+}
+
+/// switch the server into the virtual-time mode, before any router is created.
+pub fn enable(fast: bool) {
+    *SIMULATOR.lock().unwrap() = Simulator::new(fast); 
+    ENABLED.store(true, Relaxed); 
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Relaxed)
+}
+
+/// the virtual time of the event under processing, or the wall time since the server boot.
+pub fn now() -> Duration {
+    if enabled() {
+        SIMULATOR.lock().unwrap().now
+    } else {
+        BOOT.elapsed()
+    }
+}
+
+/// schedule the event `after` the current virtual time.
+pub fn schedule(after: Duration, event: Event) {
+    let mut sim = SIMULATOR.lock().unwrap(); 
+    let at = sim.now + after; 
+    sim.push(at, event); 
+    drop(sim); 
+    WAKE.notify_one(); 
+}
+
+/// the time a packet from a client arriving now gets, on the clock of `now`.
+pub fn ingress_now() -> Duration {
+    if enabled() {
+        let sim = SIMULATOR.lock().unwrap(); 
+        sim.virtual_of(Instant::now()).max(sim.now)
+    } else {
+        BOOT.elapsed()
+    }
+}
+
+/// a packet from a client, it arrives `after` the virtual time matching the wall clock.
+pub fn inject(router: Ipv4Addr, message: Message, after: Duration) {
+    let mut sim = SIMULATOR.lock().unwrap(); 
+    let at = sim.virtual_of(Instant::now()).max(sim.now) + after; 
+    sim.push(at, Event::Arrival(router, message)); 
+    drop(sim); 
+    WAKE.notify_one(); 
+}
+
+/// process the events in the order of virtual time, forever.
+pub async fn run(sender: Arc<UdpSocket>) {
+    loop {
+        let next = SIMULATOR.lock().unwrap().pop(); 
+        match next {
+            Some(Ok(event)) => process(event, &sender).await, 
+            Some(Err(wall)) => select! {
+                _ = sleep_until(wall) => {}, 
+                _ = WAKE.notified() => {}, 
+            }, 
+            None => WAKE.notified().await, 
+        }
+    }
+}
+
+async fn process(event: Event, sender: &UdpSocket) {
+    let ip = match event {
+        Event::Arrival(ip, _) | Event::TransmitDone(ip, _) | Event::RouteUpdate(ip) | Event::ReassemblyTimeout(ip) => ip, 
+    }; 
+    let router = GLOBAL_ROUTERS.lock().await.get(&ip).cloned(); 
+    let Some(router) = router else {
+        eprintln!("\x1b[31;1m[{:21}] no router exists (ip={ip})\x1b[0m", "Event Lost"); 
+        return
+    }; 
+    let started = match event {
+        Event::Arrival(_, m) => router.arrive(m, sender).await, 
+        Event::TransmitDone(_, p) => router.transmit_done(p).await.map(|t| (p, t)), 
+        Event::RouteUpdate(_) => {
+            router.update_routes().await; 
+            schedule(routing::period(), Event::RouteUpdate(ip)); 
+            None
+        }, 
+        Event::ReassemblyTimeout(_) => {
+            router.expire_fragments().await; 
+            None
+        }, 
+    }; 
+    if let Some((p, t)) = started {
+        schedule(t, Event::TransmitDone(ip, p)); 
+    }
+}